@@ -0,0 +1,132 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A hash-consing arena for keeping many expressions that share subtrees,
+//! such as the snapshots of a debugging session, the calls of a trace, or
+//! the expressions searched by the prover.
+//!
+//! Each distinct node is stored once, so an expression interned again, or
+//! a subtree that appears in many expressions, takes no more space. Handles
+//! to the same arena are equal exactly when their expressions are, so they
+//! can be compared and hashed in constant time. `Expr` itself is unchanged,
+//! and expressions are interned and rebuilt at the edges.
+//!
+//! Evaluation does not use the arena. `Context::small_step` still rewrites
+//! owned `Expr` trees, which take time linear in their size to compare or
+//! clone.
+
+use crate::core::*;
+
+/// A handle to an expression in an `ExprArena`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExprRef(u32);
+
+/// A node of an interned expression, whose children are handles.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Node {
+    Intrinsic(Intrinsic),
+    Call(TermSymbol),
+    Quote(ExprRef),
+    Compose(Vec<ExprRef>),
+    StackContext(StackId, ExprRef),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExprArena {
+    nodes: Vec<Node>,
+    index: Map<Node, ExprRef>,
+}
+
+impl ExprArena {
+    /// Intern `e`, reusing the nodes of any of its subtrees that were
+    /// interned before.
+    pub fn intern(&mut self, e: &Expr) -> ExprRef {
+        let node = match e {
+            Expr::Intrinsic(intr) => Node::Intrinsic(*intr),
+            Expr::Call(sym) => Node::Call(*sym),
+            Expr::Quote(e) => Node::Quote(self.intern(e)),
+            Expr::Compose(es) => Node::Compose(es.iter().map(|e| self.intern(e)).collect()),
+            Expr::StackContext(s, e) => Node::StackContext(*s, self.intern(e)),
+        };
+        self.insert(node)
+    }
+
+    fn insert(&mut self, node: Node) -> ExprRef {
+        if let Some(r) = self.index.get(&node) {
+            return *r;
+        }
+        let r = ExprRef(self.nodes.len() as u32);
+        self.nodes.push(node.clone());
+        self.index.insert(node, r);
+        r
+    }
+
+    pub fn node(&self, r: ExprRef) -> &Node {
+        &self.nodes[r.0 as usize]
+    }
+
+    /// Rebuild the expression that `r` is a handle to.
+    pub fn expr(&self, r: ExprRef) -> Expr {
+        match self.node(r) {
+            Node::Intrinsic(intr) => Expr::Intrinsic(*intr),
+            Node::Call(sym) => Expr::Call(*sym),
+            Node::Quote(r) => Expr::Quote(Box::new(self.expr(*r))),
+            Node::Compose(rs) => Expr::Compose(rs.iter().map(|r| self.expr(*r)).collect()),
+            Node::StackContext(s, r) => Expr::StackContext(*s, Box::new(self.expr(*r))),
+        }
+    }
+
+    /// The number of distinct nodes interned.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    fn intern_value(&mut self, v: &Value) -> ExprRef {
+        let node = match v {
+            Value::Call(sym) => Node::Call(*sym),
+            Value::Quote(e) => Node::Quote(self.intern(e)),
+        };
+        self.insert(node)
+    }
+
+    /// Intern the values of `vms`, as the expressions that push them.
+    pub(crate) fn intern_multistack(&mut self, vms: &ValueMultistack) -> InternedMultistack {
+        InternedMultistack(
+            vms.iter()
+                .map(|(s, vs)| {
+                    let values = vs.0.iter().map(|v| self.intern_value(v)).collect();
+                    (s, values)
+                })
+                .collect(),
+        )
+    }
+
+    /// Rebuild the multistack that `vms` was interned from.
+    pub(crate) fn multistack(&self, vms: &InternedMultistack) -> ValueMultistack {
+        vms.0
+            .iter()
+            .map(|(s, values)| {
+                let values = values
+                    .iter()
+                    .map(|r| match self.node(*r) {
+                        Node::Call(sym) => Value::Call(*sym),
+                        Node::Quote(r) => Value::Quote(Box::new(self.expr(*r))),
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                (*s, ValueStack(values))
+            })
+            .collect()
+    }
+}
+
+/// A multistack whose values are interned in an `ExprArena`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InternedMultistack(Vec<(StackId, Vec<ExprRef>)>);
@@ -4,6 +4,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use lasso::Key;
 pub(crate) use lasso::Rodeo as Interner;
 use std::hash::Hash;
 
@@ -64,34 +65,155 @@ impl Default for Expr {
 }
 
 impl Expr {
+    /// Increment the index of every stack context that is nested in a
+    /// context with the same symbol, where quotes start afresh. Indices are
+    /// incremented from whatever they were, so deshadowing again increments
+    /// them again.
     pub(crate) fn deshadow(&mut self) {
-        self._deshadow(&mut Map::default());
+        self.deshadow_in(&mut vec![]);
     }
 
-    fn _deshadow(&mut self, max_stack_symbol_index: &mut Map<StackSymbol, u32>) {
+    /// Deshadow `self` as if it were nested in stack contexts with the
+    /// `enclosing` symbols.
+    fn deshadow_in(&mut self, enclosing: &mut Vec<StackSymbol>) {
         match self {
             Expr::Intrinsic(_) => {}
             Expr::Call(_) => {}
-            Expr::Quote(e) => e._deshadow(&mut Map::default()),
+            Expr::Quote(e) => e.deshadow_in(&mut vec![]),
             Expr::Compose(es) => {
                 for e in es {
-                    e._deshadow(max_stack_symbol_index);
+                    e.deshadow_in(enclosing);
                 }
             }
             Expr::StackContext(s, e) => {
-                if max_stack_symbol_index.contains_key(&s.0) {
+                if enclosing.contains(&s.0) {
                     s.1 += 1;
-                    *max_stack_symbol_index.get_mut(&s.0).unwrap() += 1;
-                    e._deshadow(max_stack_symbol_index);
-                    *max_stack_symbol_index.get_mut(&s.0).unwrap() -= 1;
+                    e.deshadow_in(enclosing);
                 } else {
-                    max_stack_symbol_index.insert(s.0, 0);
-                    e._deshadow(max_stack_symbol_index);
-                    max_stack_symbol_index.remove(&s.0);
+                    enclosing.push(s.0);
+                    e.deshadow_in(enclosing);
+                    enclosing.pop();
                 }
             }
         }
     }
+
+    /// A copy of `self` deshadowed as by `deshadow_in`, made in one pass.
+    ///
+    /// Each call and apply deshadows its body this way, so it still walks
+    /// the whole body. Freshening once per definition or quote would need
+    /// subscripts relative to the enclosing contexts, but they are absolute
+    /// everywhere else, from the multistack to traces.
+    pub(crate) fn deshadowed_in(&self, enclosing: &mut Vec<StackSymbol>) -> Expr {
+        match self {
+            Expr::Intrinsic(_) | Expr::Call(_) => self.clone(),
+            Expr::Quote(e) => Expr::Quote(Box::new(e.deshadowed_in(&mut vec![]))),
+            Expr::Compose(es) => {
+                Expr::Compose(es.iter().map(|e| e.deshadowed_in(enclosing)).collect())
+            }
+            Expr::StackContext(s, e) => {
+                if enclosing.contains(&s.0) {
+                    let e = e.deshadowed_in(enclosing);
+                    Expr::StackContext(StackId(s.0, s.1 + 1), Box::new(e))
+                } else {
+                    enclosing.push(s.0);
+                    let e = e.deshadowed_in(enclosing);
+                    enclosing.pop();
+                    Expr::StackContext(*s, Box::new(e))
+                }
+            }
+        }
+    }
+
+    /// Rename the stacks that are bound, in that they are only named by
+    /// stack contexts nested in a context with the same symbol, so that they
+    /// are numbered from 1 for each symbol in the order they first appear,
+    /// skipping the numbers of free stacks. Expressions that differ only in
+    /// how their bound stacks are numbered, such as `(s|(s'1|e))` and
+    /// `(s|(s'2|e))`, have the same canonical form.
+    pub fn canonicalize(&mut self) {
+        let mut free = vec![];
+        let mut bound = vec![];
+        self.collect_stack_ids(&mut Map::default(), &mut free, &mut bound);
+        let mut renaming: Map<StackId, StackId> = Map::default();
+        let mut next_index: Map<StackSymbol, u32> = Map::default();
+        for s in bound {
+            if free.contains(&s) {
+                continue;
+            }
+            let index = next_index.entry(s.0).or_insert(1);
+            while free.contains(&StackId(s.0, *index)) {
+                *index += 1;
+            }
+            renaming.insert(s, StackId(s.0, *index));
+            *index += 1;
+        }
+        self.rename_stacks(&renaming);
+    }
+
+    /// Returns true if `self` and `other` are the same once their bound
+    /// stacks are renamed, as by `canonicalize`.
+    pub fn alpha_eq(&self, other: &Expr) -> bool {
+        let mut e1 = self.clone();
+        e1.canonicalize();
+        let mut e2 = other.clone();
+        e2.canonicalize();
+        e1 == e2
+    }
+
+    fn collect_stack_ids(
+        &self,
+        enclosing: &mut Map<StackSymbol, usize>,
+        free: &mut Vec<StackId>,
+        bound: &mut Vec<StackId>,
+    ) {
+        match self {
+            Expr::Intrinsic(_) => {}
+            Expr::Call(_) => {}
+            Expr::Quote(e) => e.collect_stack_ids(&mut Map::default(), free, bound),
+            Expr::Compose(es) => {
+                for e in es {
+                    e.collect_stack_ids(enclosing, free, bound);
+                }
+            }
+            Expr::StackContext(s, e) => {
+                let ids = if enclosing.contains_key(&s.0) {
+                    &mut *bound
+                } else {
+                    &mut *free
+                };
+                if !ids.contains(s) {
+                    ids.push(*s);
+                }
+                *enclosing.entry(s.0).or_default() += 1;
+                e.collect_stack_ids(enclosing, free, bound);
+                let count = enclosing.get_mut(&s.0).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    enclosing.remove(&s.0);
+                }
+            }
+        }
+    }
+
+    fn rename_stacks(&mut self, renaming: &Map<StackId, StackId>) {
+        match self {
+            Expr::Intrinsic(_) => {}
+            Expr::Call(_) => {}
+            Expr::Quote(e) => e.rename_stacks(renaming),
+            Expr::Compose(es) => {
+                for e in es {
+                    e.rename_stacks(renaming);
+                }
+            }
+            Expr::StackContext(s, e) => {
+                if let Some(new_s) = renaming.get(s) {
+                    *s = *new_s;
+                }
+                e.rename_stacks(renaming);
+            }
+        }
+    }
 }
 
 ///////////////
@@ -107,8 +229,54 @@ pub enum Value {
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct ValueStack(pub(crate) Vec<Value>);
 
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
-pub struct ValueMultistack(pub(crate) Map<StackId, ValueStack>);
+/// The value stacks, found by the index of their symbol in the interner and
+/// then by their subscript, so that no hashing is needed to find one. Since
+/// deshadowing keeps incrementing subscripts, a long evaluation can reach
+/// subscripts above `MAX_DENSE_SUBSCRIPT`, as can stacks written with large
+/// subscripts by hand. Those are kept in a map instead, so that the dense
+/// slots stay small. A stack without values is the same as one that was
+/// never used.
+#[derive(Debug, Clone, Default)]
+pub struct ValueMultistack {
+    dense: Vec<Vec<ValueStack>>,
+    sparse: Map<StackId, ValueStack>,
+    /// The number of stacks that have values.
+    len: usize,
+}
+
+/// The largest subscript of a stack that is kept in a dense slot.
+const MAX_DENSE_SUBSCRIPT: u32 = 4096;
+
+/// A stack of a `ValueMultistack` that is being changed, which updates the
+/// number of stacks with values once it is dropped.
+pub(crate) struct StackMut<'a> {
+    vs: &'a mut ValueStack,
+    len: &'a mut usize,
+    was_empty: bool,
+}
+
+impl std::ops::Deref for StackMut<'_> {
+    type Target = ValueStack;
+    fn deref(&self) -> &ValueStack {
+        self.vs
+    }
+}
+
+impl std::ops::DerefMut for StackMut<'_> {
+    fn deref_mut(&mut self) -> &mut ValueStack {
+        self.vs
+    }
+}
+
+impl Drop for StackMut<'_> {
+    fn drop(&mut self) {
+        match (self.was_empty, self.vs.0.is_empty()) {
+            (true, false) => *self.len += 1,
+            (false, true) => *self.len -= 1,
+            _ => {}
+        }
+    }
+}
 
 impl From<Value> for Expr {
     fn from(v: Value) -> Expr {
@@ -120,8 +288,92 @@ impl From<Value> for Expr {
 }
 
 impl ValueMultistack {
-    fn remove_empty_stacks(&mut self) {
-        self.0.retain(|_s, vs| !vs.0.is_empty());
+    /// The stack `s`, or `None` if it has no values.
+    pub(crate) fn get(&self, s: &StackId) -> Option<&ValueStack> {
+        let vs = if s.1 > MAX_DENSE_SUBSCRIPT {
+            self.sparse.get(s)?
+        } else {
+            self.dense.get(s.0 .0.into_usize())?.get(s.1 as usize)?
+        };
+        if vs.0.is_empty() {
+            None
+        } else {
+            Some(vs)
+        }
+    }
+
+    /// The stack `s`, which may be empty.
+    pub(crate) fn stack_mut(&mut self, s: StackId) -> StackMut<'_> {
+        let vs = if s.1 > MAX_DENSE_SUBSCRIPT {
+            self.sparse.entry(s).or_default()
+        } else {
+            let (i, j) = (s.0 .0.into_usize(), s.1 as usize);
+            if self.dense.len() <= i {
+                self.dense.resize_with(i + 1, Vec::new);
+            }
+            let stacks = &mut self.dense[i];
+            if stacks.len() <= j {
+                stacks.resize_with(j + 1, ValueStack::default);
+            }
+            &mut stacks[j]
+        };
+        StackMut {
+            was_empty: vs.0.is_empty(),
+            vs,
+            len: &mut self.len,
+        }
+    }
+
+    /// Replace the values of `s` with `vs`.
+    pub(crate) fn insert(&mut self, s: StackId, vs: ValueStack) {
+        *self.stack_mut(s) = vs;
+    }
+
+    /// Take the values of `s`, or return `None` if it has none.
+    pub(crate) fn remove(&mut self, s: &StackId) -> Option<ValueStack> {
+        self.get(s)?;
+        Some(std::mem::take(&mut *self.stack_mut(*s)))
+    }
+
+    /// The stacks that have values, in no particular order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (StackId, &ValueStack)> {
+        let dense = self.dense.iter().enumerate().flat_map(|(i, stacks)| {
+            let sym = StackSymbol(lasso::Spur::try_from_usize(i).unwrap());
+            stacks
+                .iter()
+                .enumerate()
+                .map(move |(j, vs)| (StackId(sym, j as u32), vs))
+        });
+        dense
+            .chain(self.sparse.iter().map(|(s, vs)| (*s, vs)))
+            .filter(|(_, vs)| !vs.0.is_empty())
+    }
+
+    /// The number of stacks that have values.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl PartialEq for ValueMultistack {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(s, vs)| other.get(&s) == Some(vs))
+    }
+}
+
+impl Eq for ValueMultistack {}
+
+impl std::iter::FromIterator<(StackId, ValueStack)> for ValueMultistack {
+    fn from_iter<I: IntoIterator<Item = (StackId, ValueStack)>>(iter: I) -> Self {
+        let mut vms = ValueMultistack::default();
+        for (s, vs) in iter {
+            vms.insert(s, vs);
+        }
+        vms
     }
 }
 
@@ -248,193 +500,140 @@ impl Context {
                         match &mut (**eii) {
                             Expr::Intrinsic(intr) => match intr {
                                 Intrinsic::Push => {
-                                    if !vms.0.contains_key(si) {
+                                    let mut vsi = vms.stack_mut(*si);
+                                    if vsi.0.len() < 1 {
                                         Err(EvalError::TooFewValues {
-                                            available: 0,
+                                            available: vsi.0.len(),
                                             expected: 1,
                                         })
                                     } else {
-                                        let vsi = vms.0.entry(*si).or_default();
-                                        if vsi.0.len() < 1 {
-                                            Err(EvalError::TooFewValues {
-                                                available: vsi.0.len(),
-                                                expected: 1,
-                                            })
-                                        } else {
-                                            let v = vsi.0.pop().unwrap();
-                                            let vsii = vms.0.entry(*sii).or_default();
-                                            vsii.0.push(v);
-                                            vms.remove_empty_stacks();
-                                            *e = Expr::default();
-                                            Ok(SmallStepRule::IntrPush)
-                                        }
+                                        let v = vsi.0.pop().unwrap();
+                                        drop(vsi);
+                                        let mut vsii = vms.stack_mut(*sii);
+                                        vsii.0.push(v);
+                                        *e = Expr::default();
+                                        Ok(SmallStepRule::IntrPush)
                                     }
                                 }
                                 Intrinsic::Pop => {
-                                    if !vms.0.contains_key(sii) {
+                                    let mut vsii = vms.stack_mut(*sii);
+                                    if vsii.0.len() < 1 {
                                         Err(EvalError::TooFewValues {
-                                            available: 0,
+                                            available: vsii.0.len(),
                                             expected: 1,
                                         })
                                     } else {
-                                        let vsii = vms.0.entry(*sii).or_default();
-                                        if vsii.0.len() < 1 {
-                                            Err(EvalError::TooFewValues {
-                                                available: vsii.0.len(),
-                                                expected: 1,
-                                            })
-                                        } else {
-                                            let v = vsii.0.pop().unwrap();
-                                            let vsi = vms.0.entry(*si).or_default();
-                                            vsi.0.push(v);
-                                            vms.remove_empty_stacks();
-                                            *e = Expr::default();
-                                            Ok(SmallStepRule::IntrPop)
-                                        }
+                                        let v = vsii.0.pop().unwrap();
+                                        drop(vsii);
+                                        let mut vsi = vms.stack_mut(*si);
+                                        vsi.0.push(v);
+                                        *e = Expr::default();
+                                        Ok(SmallStepRule::IntrPop)
                                     }
                                 }
                                 Intrinsic::Clone => {
-                                    if !vms.0.contains_key(sii) {
+                                    let mut vs = vms.stack_mut(*sii);
+                                    if vs.0.len() < 1 {
                                         Err(EvalError::TooFewValues {
-                                            available: 0,
+                                            available: vs.0.len(),
                                             expected: 1,
                                         })
                                     } else {
-                                        let vs = vms.0.entry(*sii).or_default();
-                                        if vs.0.len() < 1 {
-                                            Err(EvalError::TooFewValues {
-                                                available: vs.0.len(),
-                                                expected: 1,
-                                            })
-                                        } else {
-                                            vs.0.push(vs.0.last().unwrap().clone());
-                                            *e = Expr::default();
-                                            Ok(SmallStepRule::IntrClone)
-                                        }
+                                        let v = vs.0.last().unwrap().clone();
+                                        vs.0.push(v);
+                                        *e = Expr::default();
+                                        Ok(SmallStepRule::IntrClone)
                                     }
                                 }
                                 Intrinsic::Drop => {
-                                    if !vms.0.contains_key(sii) {
+                                    let mut vs = vms.stack_mut(*sii);
+                                    if vs.0.len() < 1 {
                                         Err(EvalError::TooFewValues {
-                                            available: 0,
+                                            available: vs.0.len(),
                                             expected: 1,
                                         })
                                     } else {
-                                        let vs = vms.0.entry(*sii).or_default();
-                                        if vs.0.len() < 1 {
-                                            Err(EvalError::TooFewValues {
-                                                available: vs.0.len(),
-                                                expected: 1,
-                                            })
-                                        } else {
-                                            vs.0.pop();
-                                            vms.remove_empty_stacks();
-                                            *e = Expr::default();
-                                            Ok(SmallStepRule::IntrDrop)
-                                        }
+                                        vs.0.pop();
+                                        *e = Expr::default();
+                                        Ok(SmallStepRule::IntrDrop)
                                     }
                                 }
                                 Intrinsic::Quote => {
-                                    if !vms.0.contains_key(sii) {
+                                    let mut vs = vms.stack_mut(*sii);
+                                    if vs.0.len() < 1 {
                                         Err(EvalError::TooFewValues {
-                                            available: 0,
+                                            available: vs.0.len(),
                                             expected: 1,
                                         })
                                     } else {
-                                        let vs = vms.0.entry(*sii).or_default();
-                                        if vs.0.len() < 1 {
-                                            Err(EvalError::TooFewValues {
-                                                available: vs.0.len(),
-                                                expected: 1,
-                                            })
-                                        } else {
-                                            let v = vs.0.pop().unwrap();
-                                            let qe = Expr::from(v);
-                                            vs.0.push(Value::Quote(Box::new(qe)));
-                                            *e = Expr::default();
-                                            Ok(SmallStepRule::IntrQuote)
-                                        }
+                                        let v = vs.0.pop().unwrap();
+                                        let qe = Expr::from(v);
+                                        vs.0.push(Value::Quote(Box::new(qe)));
+                                        *e = Expr::default();
+                                        Ok(SmallStepRule::IntrQuote)
                                     }
                                 }
                                 Intrinsic::Compose => {
-                                    if !vms.0.contains_key(sii) {
+                                    let mut vs = vms.stack_mut(*sii);
+                                    if vs.0.len() < 2 {
                                         Err(EvalError::TooFewValues {
-                                            available: 0,
+                                            available: vs.0.len(),
                                             expected: 2,
                                         })
                                     } else {
-                                        let vs = vms.0.entry(*sii).or_default();
-                                        if vs.0.len() < 2 {
-                                            Err(EvalError::TooFewValues {
-                                                available: vs.0.len(),
-                                                expected: 2,
-                                            })
+                                        let e2 = self.unquote_value(vs.0.pop().unwrap())?;
+                                        let e1 = self.unquote_value(vs.0.pop().unwrap())?;
+                                        let mut new_es = match (e1, e2) {
+                                            (Expr::Compose(mut e1s), Expr::Compose(mut e2s)) => {
+                                                e1s.extend(e2s.drain(..));
+                                                e1s
+                                            }
+                                            (Expr::Compose(mut e1s), e2) => {
+                                                e1s.push(e2);
+                                                e1s
+                                            }
+                                            (e1, Expr::Compose(mut e2s)) => {
+                                                e2s.insert(0, e1);
+                                                e2s
+                                            }
+                                            (e1, e2) => vec![e1, e2],
+                                        };
+                                        let new_e = if new_es.len() == 1 {
+                                            new_es.drain(..).next().unwrap()
                                         } else {
-                                            let e2 = self.unquote_value(vs.0.pop().unwrap())?;
-                                            let e1 = self.unquote_value(vs.0.pop().unwrap())?;
-                                            let mut new_es = match (e1, e2) {
-                                                (
-                                                    Expr::Compose(mut e1s),
-                                                    Expr::Compose(mut e2s),
-                                                ) => {
-                                                    e1s.extend(e2s.drain(..));
-                                                    e1s
-                                                }
-                                                (Expr::Compose(mut e1s), e2) => {
-                                                    e1s.push(e2);
-                                                    e1s
-                                                }
-                                                (e1, Expr::Compose(mut e2s)) => {
-                                                    e2s.insert(0, e1);
-                                                    e2s
-                                                }
-                                                (e1, e2) => vec![e1, e2],
-                                            };
-                                            let new_e = if new_es.len() == 1 {
-                                                new_es.drain(..).next().unwrap()
-                                            } else {
-                                                Expr::Compose(new_es)
-                                            };
-                                            vs.0.push(Value::Quote(Box::new(new_e)));
-                                            *e = Expr::default();
-                                            Ok(SmallStepRule::IntrCompose)
-                                        }
+                                            Expr::Compose(new_es)
+                                        };
+                                        vs.0.push(Value::Quote(Box::new(new_e)));
+                                        *e = Expr::default();
+                                        Ok(SmallStepRule::IntrCompose)
                                     }
                                 }
                                 Intrinsic::Apply => {
-                                    if !vms.0.contains_key(sii) {
+                                    let mut vs = vms.stack_mut(*sii);
+                                    if vs.0.len() < 1 {
                                         Err(EvalError::TooFewValues {
-                                            available: 0,
+                                            available: vs.0.len(),
                                             expected: 1,
                                         })
                                     } else {
-                                        let vs = vms.0.entry(*sii).or_default();
-                                        if vs.0.len() < 1 {
-                                            Err(EvalError::TooFewValues {
-                                                available: vs.0.len(),
-                                                expected: 1,
-                                            })
-                                        } else {
-                                            let e1 = self.unquote_value(vs.0.pop().unwrap())?;
-                                            vms.remove_empty_stacks();
-                                            *eii = Box::new(e1);
-                                            e.deshadow();
-                                            Ok(SmallStepRule::IntrApply)
-                                        }
+                                        let mut e1 = self.unquote_value(vs.0.pop().unwrap())?;
+                                        e1.deshadow_in(&mut redex_symbols(*si, sii));
+                                        *eii = Box::new(e1);
+                                        Ok(SmallStepRule::IntrApply)
                                     }
                                 }
                             },
                             Expr::Call(sym) => {
                                 if let Some(new_e) = self.terms.get(sym) {
-                                    *eii = Box::new(new_e.clone());
-                                    e.deshadow();
+                                    let mut enclosing = redex_symbols(*si, sii);
+                                    **eii = new_e.deshadowed_in(&mut enclosing);
                                     Ok(SmallStepRule::LitCall)
                                 } else {
                                     Err(EvalError::UndefinedTerm(*sym))
                                 }
                             }
                             Expr::Quote(qe) => {
-                                let vs = vms.0.entry(*sii).or_default();
+                                let mut vs = vms.stack_mut(*sii);
                                 vs.0.push(Value::Quote(qe.clone()));
                                 *e = Expr::default();
                                 Ok(SmallStepRule::LitQuote)
@@ -473,6 +672,19 @@ impl Context {
     }
 }
 
+/// Deshadow the stack contexts of a redex `(si|(sii|e))`, other than `e`,
+/// and return the symbols that `e` is nested in, so that deshadowing `e` in
+/// them gives the same result as deshadowing the whole redex, without
+/// walking it twice.
+fn redex_symbols(si: StackId, sii: &mut StackId) -> Vec<StackSymbol> {
+    if sii.0 == si.0 {
+        sii.1 += 1;
+        vec![si.0]
+    } else {
+        vec![si.0, sii.0]
+    }
+}
+
 //////////////////////
 // Term Definitions //
 //////////////////////
@@ -0,0 +1,259 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::arena::*;
+use crate::core::*;
+
+/// What a call expands: a term (`LitCall`) or a quoted value (`IntrApply`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Callee {
+    Term(TermSymbol),
+    Apply,
+}
+
+/// How a call was skipped, rather than evaluated step by step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Skip {
+    /// The call's result was memoized.
+    Memo,
+    /// A jet computed the call's result natively.
+    Jet,
+}
+
+/// A call whose expansion has not been fully evaluated yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Frame {
+    pub(crate) callee: Callee,
+    /// The step at which the call was expanded.
+    pub(crate) step: usize,
+    /// The number of top-level expressions that followed the call when it
+    /// was expanded. Small steps only ever rewrite the first top-level
+    /// expression, so the call has returned once no more than this many
+    /// remain.
+    rest_len: usize,
+}
+
+/// The calls that are currently being evaluated, outermost first.
+///
+/// This is reconstructed from the shape of the expression, since the
+/// calculus itself has no notion of a call stack.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct CallStack(Vec<Frame>);
+
+impl CallStack {
+    pub(crate) fn frames(&self) -> &[Frame] {
+        &self.0
+    }
+
+    pub(crate) fn depth(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Update the call stack after a small step turned the expression into
+    /// `e`. `callee` is the result of `next_callee` before the step.
+    pub(crate) fn update(
+        &mut self,
+        callee: Option<Callee>,
+        rule: SmallStepRule,
+        e: &Expr,
+        step: usize,
+    ) {
+        self.pop_returned(e);
+        let len = top_len(e);
+        match (callee, rule) {
+            (Some(callee @ Callee::Term(_)), SmallStepRule::LitCall)
+            | (Some(callee @ Callee::Apply), SmallStepRule::IntrApply) => self.0.push(Frame {
+                callee,
+                step,
+                rest_len: len - 1,
+            }),
+            _ => {}
+        }
+    }
+
+    /// Pop the calls that have returned, now that the expression is `e`.
+    pub(crate) fn pop_returned(&mut self, e: &Expr) {
+        let len = top_len(e);
+        while let Some(frame) = self.0.last() {
+            if len <= frame.rest_len {
+                self.0.pop();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// The number of top-level expressions in `e`.
+fn top_len(e: &Expr) -> usize {
+    match e {
+        Expr::Compose(es) => es.len(),
+        _ => 1,
+    }
+}
+
+/// Returns the redex that the next small step of `e` will reduce.
+pub(crate) fn redex(e: &Expr) -> &Expr {
+    match e {
+        Expr::Compose(es) if !es.is_empty() => redex(&es[0]),
+        _ => e,
+    }
+}
+
+/// If the next small step of `e` is a `LitCall` or an `IntrApply`, returns
+/// what it will call.
+pub(crate) fn next_callee(e: &Expr) -> Option<Callee> {
+    match redex(e) {
+        Expr::StackContext(_, ei) => match &**ei {
+            Expr::StackContext(_, eii) => match &**eii {
+                Expr::Call(sym) => Some(Callee::Term(*sym)),
+                Expr::Intrinsic(Intrinsic::Apply) => Some(Callee::Apply),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Stops evaluation when a stack changes, or when it holds more than
+/// `max_depth` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Watchpoint {
+    pub(crate) stack: StackId,
+    pub(crate) max_depth: Option<usize>,
+}
+
+/// The number of steps between the checkpoints that a debugging session
+/// replays from when rewinding.
+const CHECKPOINT_INTERVAL: usize = 256;
+
+/// A snapshot of a debugging session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Checkpoint {
+    step: usize,
+    vms: InternedMultistack,
+    e: ExprRef,
+    calls: CallStack,
+}
+
+/// How far a debugging session should run before pausing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RunUntil {
+    /// Take a single small step.
+    Step,
+    /// Run until the call stack is no deeper than this.
+    Depth(usize),
+    /// Run until this step.
+    At(usize),
+    /// Run until a breakpoint, a watchpoint, or the end of the evaluation.
+    End,
+}
+
+/// An evaluation that is being debugged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Debugger {
+    pub(crate) e: Expr,
+    pub(crate) calls: CallStack,
+    pub(crate) step: usize,
+    pub(crate) until: RunUntil,
+    /// The number of steps taken since the session was last resumed.
+    pub(crate) run_steps: usize,
+    /// Snapshots taken every `CHECKPOINT_INTERVAL` steps, oldest first.
+    /// Earlier steps are restored by replaying `small_step` from these,
+    /// which is only sound as long as nothing else changes the definitions
+    /// or the multistack.
+    checkpoints: Vec<Checkpoint>,
+    /// The expressions and values of the checkpoints, which share most of
+    /// their subtrees.
+    arena: ExprArena,
+    /// Whether something else may have changed the definitions or the
+    /// multistack since the last step.
+    pub(crate) stale: bool,
+}
+
+impl Debugger {
+    pub(crate) fn new(vms: &ValueMultistack, e: Expr) -> Self {
+        let mut debugger = Self {
+            e,
+            calls: CallStack::default(),
+            step: 0,
+            until: RunUntil::Step,
+            run_steps: 0,
+            checkpoints: vec![],
+            arena: ExprArena::default(),
+            stale: false,
+        };
+        let checkpoint = debugger.checkpoint(vms);
+        debugger.checkpoints.push(checkpoint);
+        debugger
+    }
+
+    fn checkpoint(&mut self, vms: &ValueMultistack) -> Checkpoint {
+        Checkpoint {
+            step: self.step,
+            vms: self.arena.intern_multistack(vms),
+            e: self.arena.intern(&self.e),
+            calls: self.calls.clone(),
+        }
+    }
+
+    /// Take one small step, updating the call stack and the checkpoints.
+    pub(crate) fn step(
+        &mut self,
+        ctx: &mut Context,
+        vms: &mut ValueMultistack,
+    ) -> Result<SmallStepRule, EvalError> {
+        if self.stale {
+            self.forget_history(vms);
+        }
+        let callee = next_callee(&self.e);
+        let rule = ctx.small_step(vms, &mut self.e)?;
+        self.step += 1;
+        self.calls.update(callee, rule, &self.e, self.step);
+        if self.step >= self.checkpoints.last().unwrap().step + CHECKPOINT_INTERVAL {
+            let checkpoint = self.checkpoint(vms);
+            self.checkpoints.push(checkpoint);
+        }
+        Ok(rule)
+    }
+
+    /// The earliest step that the session can be rewound to.
+    pub(crate) fn first_step(&self) -> usize {
+        if self.stale {
+            self.step
+        } else {
+            self.checkpoints[0].step
+        }
+    }
+
+    /// Forget every step before the current one, since replaying them may
+    /// no longer reproduce the current state.
+    fn forget_history(&mut self, vms: &ValueMultistack) {
+        self.arena = ExprArena::default();
+        self.checkpoints = vec![self.checkpoint(vms)];
+        self.stale = false;
+    }
+
+    /// Restore the exact state of an earlier step, by replaying from the
+    /// latest checkpoint before it.
+    pub(crate) fn rewind(&mut self, ctx: &mut Context, vms: &mut ValueMultistack, step: usize) {
+        assert!(self.first_step() <= step && step <= self.step);
+        if self.stale {
+            return;
+        }
+        let i = self.checkpoints.partition_point(|cp| cp.step <= step) - 1;
+        let checkpoint = &self.checkpoints[i];
+        *vms = self.arena.multistack(&checkpoint.vms);
+        self.e = self.arena.expr(checkpoint.e);
+        self.calls = checkpoint.calls.clone();
+        self.step = checkpoint.step;
+        while self.step < step {
+            // These steps succeeded before, and nothing has changed since.
+            self.step(ctx, vms).unwrap();
+        }
+    }
+}
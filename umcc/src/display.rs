@@ -78,6 +78,13 @@ where
     }
 }
 
+impl Resolve for SmallStepRule {
+    type Output = SmallStepRule;
+    fn resolve(&self, _: &Interner) -> Self::Output {
+        *self
+    }
+}
+
 impl Resolve for TermSymbol {
     type Output = ResolvedTermSymbol;
     fn resolve(&self, interner: &Interner) -> Self::Output {
@@ -137,8 +144,7 @@ impl Resolve for ValueMultistack {
     type Output = ResolvedValueMultistack;
     fn resolve(&self, interner: &Interner) -> Self::Output {
         ResolvedValueMultistack(
-            self.0
-                .iter()
+            self.iter()
                 .map(|(k, v)| (k.resolve(interner), v.resolve(interner)))
                 .collect(),
         )
@@ -257,7 +263,7 @@ fn display_resolved_value_stack(
     f: &mut fmt::Formatter,
 ) -> fmt::Result {
     use std::fmt::Display;
-    "⟨".fmt(f)?;
+    if f.alternate() { "<" } else { "⟨" }.fmt(f)?;
     s.fmt(f)?;
     "|".fmt(f)?;
     if let Some(v) = vs.0.first() {
@@ -267,9 +273,11 @@ fn display_resolved_value_stack(
         " ".fmt(f)?;
         v.fmt(f)?;
     }
-    "⟩".fmt(f)
+    if f.alternate() { ">" } else { "⟩" }.fmt(f)
 }
 
+/// Displays each stack as `⟨s|v1 v2⟩`, or as `<s|v1 v2>` if formatted with
+/// `{:#}`.
 impl fmt::Display for ResolvedValueMultistack {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut sids: Vec<ResolvedStackId> = self.0.keys().cloned().collect();
@@ -306,6 +314,49 @@ impl fmt::Display for SmallStepRule {
     }
 }
 
+/// Displays the arrow of a small step judgement that applies the rule, as
+/// `‒Rule⟶`, or as `-Rule->` in ASCII.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmallStepArrow(pub SmallStepRule);
+
+impl fmt::Display for SmallStepArrow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "-{}->", self.0)
+        } else {
+            write!(f, "‒{}⟶", self.0)
+        }
+    }
+}
+
+/// Displays the arrow of a big step judgement, as `⇓`, or as `==>` in ASCII.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BigStepArrow;
+
+impl fmt::Display for BigStepArrow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() { "==>" } else { "⇓" }.fmt(f)
+    }
+}
+
+/// Displays a value in ASCII notation if `ascii` is true, as if it were
+/// formatted with `{:#}`, and in the usual notation otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Notation<T> {
+    pub value: T,
+    pub ascii: bool,
+}
+
+impl<T: fmt::Display> fmt::Display for Notation<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ascii {
+            write!(f, "{:#}", self.value)
+        } else {
+            write!(f, "{}", self.value)
+        }
+    }
+}
+
 impl fmt::Display for ResolvedEvalError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -0,0 +1,213 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::core::*;
+use crate::display::*;
+use crate::testing::DEFAULT_MAX_STEPS;
+
+/// The largest number of values put on each stack of an input.
+pub const MAX_DEPTH: usize = 3;
+
+/// The largest number of inputs tried.
+pub const MAX_INPUTS: usize = 4096;
+
+/// How evaluating an expression from an input ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Finished(ValueMultistack),
+    Failed(EvalError),
+    /// Evaluation took more than `DEFAULT_MAX_STEPS` steps.
+    Unfinished,
+}
+
+/// The result of comparing two expressions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Equivalence {
+    /// Both expressions had the same outcome from each of `inputs` inputs.
+    /// Both failed, or both did not finish, from `inconclusive` of them, so
+    /// those inputs did not compare anything.
+    NoDifference { inputs: usize, inconclusive: usize },
+    /// The expressions had different outcomes from `input`.
+    Counterexample {
+        input: ValueMultistack,
+        left: Outcome,
+        right: Outcome,
+    },
+}
+
+/// A comparison of two expressions, which already have their stack
+/// contexts, by evaluating both from each input and comparing the outcomes.
+/// Each call to `step` tries one input, so that a long comparison can be
+/// interrupted.
+///
+/// The inputs put up to `MAX_DEPTH` values on each of the stacks named by
+/// the expressions, outside of quotes, in order of the number of values, so
+/// that a counterexample is as small as possible. There are exponentially
+/// many of them in the number of stacks, so at most `MAX_INPUTS` are tried.
+/// The values are symbolic placeholders, named `?1`, `?2`, ..., which cannot
+/// be defined as terms, so applying one fails. Each is tried both as `?n`
+/// and quoted as `[?n]`, so that quotes can be composed and applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Comparison {
+    e1: Expr,
+    e2: Expr,
+    stacks: Vec<StackId>,
+    placeholders: Vec<TermSymbol>,
+    /// The number of values to put on each stack for the next input, or
+    /// `None` once every input was tried.
+    depths: Option<Vec<usize>>,
+    /// Whether each value of the next input is quoted.
+    quoted: Vec<bool>,
+    inputs: usize,
+    inconclusive: usize,
+}
+
+impl Comparison {
+    pub(crate) fn new(ctx: &mut Context, e1: Expr, e2: Expr) -> Self {
+        let mut stacks = vec![];
+        collect_stacks(&e1, &mut stacks);
+        collect_stacks(&e2, &mut stacks);
+        {
+            let interner = &ctx.interner;
+            stacks.sort_unstable_by_key(|s| s.resolve(interner));
+        }
+        let placeholders: Vec<TermSymbol> = (1..=stacks.len() * MAX_DEPTH)
+            .map(|i| TermSymbol(ctx.interner.get_or_intern(format!("?{}", i))))
+            .collect();
+        Comparison {
+            e1,
+            e2,
+            depths: Some(vec![0; stacks.len()]),
+            quoted: vec![],
+            stacks,
+            placeholders,
+            inputs: 0,
+            inconclusive: 0,
+        }
+    }
+
+    /// Try the next input, and return the result once there is one.
+    pub(crate) fn step(&mut self, ctx: &mut Context) -> Option<Equivalence> {
+        let depths = match &mut self.depths {
+            Some(depths) if self.inputs < MAX_INPUTS => depths,
+            _ => {
+                return Some(Equivalence::NoDifference {
+                    inputs: self.inputs,
+                    inconclusive: self.inconclusive,
+                })
+            }
+        };
+        let mut input = ValueMultistack::default();
+        let mut values = self
+            .placeholders
+            .iter()
+            .zip(self.quoted.iter())
+            .map(|(sym, quoted)| {
+                if *quoted {
+                    Value::Quote(Box::new(Expr::Call(*sym)))
+                } else {
+                    Value::Call(*sym)
+                }
+            });
+        for (s, depth) in self.stacks.iter().zip(depths.iter()) {
+            let mut vs = input.stack_mut(*s);
+            vs.0.extend(values.by_ref().take(*depth));
+        }
+        if !next_quoted(&mut self.quoted) {
+            if next_depths(depths) {
+                self.quoted = vec![false; depths.iter().sum()];
+            } else {
+                self.depths = None;
+            }
+        }
+        let left = run(ctx, input.clone(), self.e1.clone());
+        let right = run(ctx, input.clone(), self.e2.clone());
+        self.inputs += 1;
+        if left != right {
+            return Some(Equivalence::Counterexample { input, left, right });
+        }
+        if let Outcome::Failed(_) | Outcome::Unfinished = left {
+            self.inconclusive += 1;
+        }
+        None
+    }
+}
+
+fn run(ctx: &mut Context, mut vms: ValueMultistack, mut e: Expr) -> Outcome {
+    let mut step = 0;
+    while e != Expr::default() {
+        if step == DEFAULT_MAX_STEPS {
+            return Outcome::Unfinished;
+        }
+        if let Err(err) = ctx.small_step(&mut vms, &mut e) {
+            return Outcome::Failed(err);
+        }
+        step += 1;
+    }
+    Outcome::Finished(vms)
+}
+
+/// The stacks named by `e`, outside of quotes.
+fn collect_stacks(e: &Expr, stacks: &mut Vec<StackId>) {
+    match e {
+        Expr::Intrinsic(_) | Expr::Call(_) | Expr::Quote(_) => {}
+        Expr::Compose(es) => {
+            for e in es {
+                collect_stacks(e, stacks);
+            }
+        }
+        Expr::StackContext(s, e) => {
+            if !stacks.contains(s) {
+                stacks.push(*s);
+            }
+            collect_stacks(e, stacks);
+        }
+    }
+}
+
+/// Advance `depths` to the next way of putting values on the stacks, with
+/// at most `MAX_DEPTH` values on each: the next with the same total, with
+/// as many values as possible on the first stacks, or else the first with
+/// one more value. Returns false if there is none.
+fn next_depths(depths: &mut [usize]) -> bool {
+    let n = depths.len();
+    // Move a value from the last stack that can give one to a later stack.
+    let mut rest = 0;
+    for i in (0..n).rev() {
+        if depths[i] > 0 && rest < (n - i - 1) * MAX_DEPTH {
+            depths[i] -= 1;
+            fill(&mut depths[i + 1..], rest + 1);
+            return true;
+        }
+        rest += depths[i];
+    }
+    // Every way with this total was tried.
+    if rest < n * MAX_DEPTH {
+        fill(depths, rest + 1);
+        return true;
+    }
+    false
+}
+
+/// Advance `quoted` to the next way of quoting the values of an input,
+/// counting in binary. Returns false if every way was tried.
+fn next_quoted(quoted: &mut [bool]) -> bool {
+    for q in quoted.iter_mut() {
+        *q = !*q;
+        if *q {
+            return true;
+        }
+    }
+    false
+}
+
+/// Put `total` values on the stacks, as many as possible on the first.
+fn fill(depths: &mut [usize], mut total: usize) {
+    for depth in depths.iter_mut() {
+        *depth = total.min(MAX_DEPTH);
+        total -= *depth;
+    }
+}
@@ -6,42 +6,219 @@
 
 use crate::builtin::TERM_DEF_SRCS;
 use crate::core::*;
+use crate::debug::*;
 use crate::display::*;
+use crate::equiv::*;
+use crate::jets::*;
+use crate::memo::*;
+use crate::optimize::*;
 use crate::parse::*;
+use crate::profile::*;
+use crate::prove::*;
+use crate::stats::*;
+use crate::testing::*;
+use crate::trace::*;
 use std::io;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Definition {
+    Term(TermDef),
+    Test(TestDef),
+    Law(LawDef),
+}
+
+#[derive(Debug, Clone)]
 pub(crate) enum InterpCommand {
-    Eval(Vec<TermDef>, Expr),
-    Trace(Expr),
+    Eval(Vec<Definition>, Expr),
+    Trace(TraceOptions, Expr),
     Show(TermSymbol),
+    Optimize(TermSymbol),
+    Equiv(Expr, Expr),
+    Prove(Expr, Expr),
+    Debug(Expr),
+    Step,
+    Next,
+    Finish,
+    Continue,
+    Where,
+    Back(usize),
+    Goto(usize),
+    Break(Option<TermSymbol>),
+    Unbreak(TermSymbol),
+    Watch(StackId, Option<usize>),
+    Unwatch(StackId),
+    Stats(Option<bool>),
+    Memo(Option<bool>),
+    Jets(Option<JetMode>),
+    Time(Expr),
+    Profile(Expr),
+    Test,
+    Assert(Judgement),
+    Ascii(bool),
     List,
-    Drop,
+    Set(Vec<(StackId, ValueStack)>),
+    Push(StackId, Vec<Value>),
+    Pop(StackId),
+    Drop(Option<StackId>),
     Clear,
     Reset,
     Help,
+    /// An `:equiv` that is comparing the expressions.
+    CheckEquiv(Comparison),
+    /// A law that is being checked before it is defined, with the
+    /// definitions and expression that followed it.
+    CheckLaw(Comparison, LawDef, Vec<Definition>, Expr),
+    /// A `:prove` that is searching for a proof.
+    SearchProof(Box<ProofSearch>),
 }
 
 pub(crate) static HELP: &'static str = "\
 Commands available:
 
    {term <sym> = <expr>}    define <sym> as <expr>
+   {test <name> [<n>]: <vms> <expr> ⇓ <vms>}
+                            define a test that <expr> takes the first value
+                              multistack to the second within <n> steps
+   {law <name>: <expr> = <expr>}
+                            define a law that the first expression can be
+                              replaced by the second, once it is checked
    <expr>                   evaluate <expr>
-   :trace <expr>            trace the evaluation of <expr>
+   :trace <opts> <expr>     trace the evaluation of <expr>, where <opts> are
+                              -hide <rule>...  hide steps using these rules
+                              -show <rule>...  only show steps using these rules
+                              -in <sym>        only show steps inside <sym>
+                              -steps <n>..<m>  only show steps <n> through <m>
+                              -every <k>       only show every <k>th step
+                              -tree            show a tree of the calls made
+                              -depth <n>       leave out calls deeper than <n>
+   :debug <expr>            debug the evaluation of <expr>
+   :step                    take one small step
+   :next                    take one step, stepping over calls
+   :finish                  run until the current call returns
+   :continue                run until a breakpoint or watchpoint
+   :where                   show the calls being evaluated
+   :back [<n>]              go back one step, or <n> steps
+   :goto <n>                go to step <n>
+   :break [<sym>]           stop on calls to <sym>, or list stops
+   :unbreak <sym>           remove the breakpoint on <sym>
+   :watch <stk> [<n>]       stop when <stk> changes or exceeds <n> values
+   :unwatch <stk>           remove the watchpoint on <stk>
+   :stats [on|off]          show statistics about the last evaluation,
+                              or turn collecting them on or off
+   :time <expr>             evaluate <expr> and show statistics about it
+   :memo [on|off]           show the hits and misses of memoized calls, or
+                              turn memoizing calls on or off
+   :jets [on|off|check]     show the uses of native implementations of
+                              builtin terms, or turn using them on or off,
+                              or on and checked against their definitions
+   :profile <expr>          evaluate <expr> and show the steps taken in each
+                              stack of calls, as collapsed stacks
+   :test                    run the defined tests
+   :assert <judgement>      check that <vms> <expr> ‒<rule>⟶ <vms> <expr>
+                              or <vms> <expr> ⇓ <vms> <expr> holds
    :show <sym>              show the definition of <sym>
+   :equiv <expr> = <expr>   look for an input multistack on which the two
+                              expressions give different results
+   :prove <expr> = <expr>   search for a proof that the two expressions are
+                              equal, using the rules and the defined laws
+   :optimize <sym>          optimize the definition of <sym>, comparing the
+                              steps a call on the current stacks and each
+                              test take before and after
    :list                    list the defined symbols
-   :drop                    drop the current value stack
+   :set <vms>               replace the stacks in <vms> with their values
+   :push <stk> <val>...     push values onto <stk>
+   :pop <stk>               pop a value from <stk>
+   :drop [<stk>]            drop the values on <stk>, or on every stack
    :clear                   clear all definitions
    :reset                   reset the interpreter
+   :ascii on|off            write ⟨s|..⟩, ‒Rule⟶ and ⇓ in ASCII, as
+                              <s|..>, -Rule-> and ==>, which are also accepted
+                              as input
    :help                    display this list of commands
 ";
 
+/// Returns true if `input` contains an unclosed `{`, `[`, or `(`, in which
+/// case the REPL should keep reading lines before interpreting it.
+pub fn is_incomplete(input: &str) -> bool {
+    let mut depth: usize = 0;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '[' | '(' => depth += 1,
+            '}' | ']' | ')' => {
+                if depth == 0 {
+                    // Unbalanced, so let the parser report the error.
+                    return false;
+                }
+                depth -= 1;
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                // Skip `--` comments
+                for c in chars.by_ref() {
+                    if c == '\n' || c == '\r' {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+/// Deshadow the quoted expressions in `values`, as if they had been quoted
+/// by evaluating an expression.
+fn deshadow_values(values: &mut [Value]) {
+    for value in values.iter_mut() {
+        if let Value::Quote(e) = value {
+            e.deshadow();
+        }
+    }
+}
+
 pub struct Interp {
     ctx: Context,
     _id: StackId,
     __id: StackId,
     vms: ValueMultistack,
     command: Option<InterpCommand>,
+    last_step: Option<Result<SmallStepRule, EvalError>>,
+    /// Whether the input to the last call to `interp_start` did not parse.
+    parse_failed: bool,
+    tracer: Tracer,
+    debugger: Option<Debugger>,
+    breakpoints: Vec<TermSymbol>,
+    watchpoints: Vec<Watchpoint>,
+    json_trace: Option<JsonTraceSink>,
+    /// Whether to collect statistics about every evaluation.
+    collect_stats: bool,
+    /// The statistics being collected about the current evaluation, if any.
+    stats: Option<Stats>,
+    /// Whether to show the statistics once the current evaluation ends.
+    report_stats: bool,
+    last_stats: Option<Stats>,
+    /// Whether to profile every evaluation.
+    collect_profile: bool,
+    /// The profile of the current evaluation, if it is being profiled.
+    profiler: Option<Profiler>,
+    /// Whether to show the profile once the current evaluation ends.
+    report_profile: bool,
+    last_profile: Option<Profiler>,
+    /// The memoized calls, if evaluations memoize calls.
+    memo: Option<Memo>,
+    /// The native implementations of builtin terms, if evaluations use
+    /// them.
+    jets: Option<Jets>,
+    tests: Vec<TestDef>,
+    laws: Vec<LawDef>,
+    /// The original definitions of the optimized terms, and the terms that
+    /// were inlined into them, in the order they were optimized.
+    optimized: Vec<(TermSymbol, Expr, Vec<TermSymbol>)>,
+    /// Whether to write output in ASCII notation.
+    ascii: bool,
+    /// Commands that the program running the interpreter handles itself,
+    /// listed after the interpreter's own by `:help`.
+    extra_help: &'static str,
 }
 
 impl Default for Interp {
@@ -61,6 +238,28 @@ impl Default for Interp {
             __id,
             vms: ValueMultistack::default(),
             command: None,
+            last_step: None,
+            parse_failed: false,
+            tracer: Tracer::default(),
+            debugger: None,
+            breakpoints: vec![],
+            watchpoints: vec![],
+            json_trace: None,
+            collect_stats: false,
+            stats: None,
+            report_stats: false,
+            last_stats: None,
+            collect_profile: false,
+            profiler: None,
+            report_profile: false,
+            last_profile: None,
+            memo: None,
+            jets: None,
+            tests: vec![],
+            laws: vec![],
+            optimized: vec![],
+            ascii: false,
+            extra_help: "",
         }
     }
 }
@@ -70,6 +269,109 @@ impl Interp {
         self.command.is_none()
     }
 
+    /// The defined terms, sorted by name.
+    pub fn definitions(&self) -> Vec<(ResolvedTermSymbol, ResolvedExpr)> {
+        let mut defs: Vec<(ResolvedTermSymbol, ResolvedExpr)> = self
+            .ctx
+            .terms
+            .iter()
+            .map(|(sym, e)| {
+                (
+                    sym.resolve(&self.ctx.interner),
+                    e.resolve(&self.ctx.interner),
+                )
+            })
+            .collect();
+        defs.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        defs
+    }
+
+    /// Write output in ASCII notation, which uses `<s|...>`, `-Rule->` and
+    /// `==>`, or in the usual notation.
+    pub fn set_ascii(&mut self, ascii: bool) {
+        self.ascii = ascii;
+    }
+
+    /// List `help`, which describes commands that the caller handles before
+    /// passing input to the interpreter, after the interpreter's own
+    /// commands in `:help`.
+    pub fn set_extra_help(&mut self, help: &'static str) {
+        self.extra_help = help;
+    }
+
+    fn notation<T>(&self, value: T) -> Notation<T> {
+        Notation {
+            value,
+            ascii: self.ascii,
+        }
+    }
+
+    /// Resolve `x` and display it in the selected notation.
+    fn show<T: Resolve>(&self, x: &T) -> Notation<T::Output> {
+        self.notation(x.resolve(&self.ctx.interner))
+    }
+
+    fn write_counterexample(
+        &self,
+        input: &ValueMultistack,
+        left: &Outcome,
+        right: &Outcome,
+        w: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        w.write_fmt(format_args!(
+            "Counterexample: {}\n  left:  {}\n  right: {}\n",
+            self.describe_values(input),
+            self.describe_outcome(left),
+            self.describe_outcome(right)
+        ))
+    }
+
+    fn describe_values(&self, vms: &ValueMultistack) -> String {
+        if vms.is_empty() {
+            "no values".to_owned()
+        } else {
+            self.show(vms).to_string()
+        }
+    }
+
+    fn describe_outcome(&self, outcome: &Outcome) -> String {
+        match outcome {
+            Outcome::Finished(vms) => self.describe_values(vms),
+            Outcome::Failed(err) => err.resolve(&self.ctx.interner).to_string(),
+            Outcome::Unfinished => format!("did not finish within {} steps", DEFAULT_MAX_STEPS),
+        }
+    }
+
+    /// The current value multistack.
+    pub fn value_multistack(&self) -> ResolvedValueMultistack {
+        self.vms.resolve(&self.ctx.interner)
+    }
+
+    /// The expression remaining to be evaluated by the current command or
+    /// debugging session, if any.
+    pub fn expr(&self) -> Option<ResolvedExpr> {
+        match (&self.command, &self.debugger) {
+            (Some(InterpCommand::Eval(_, e)), _) | (Some(InterpCommand::Trace(_, e)), _) => {
+                Some(e.resolve(&self.ctx.interner))
+            }
+            (_, Some(debugger)) => Some(debugger.e.resolve(&self.ctx.interner)),
+            _ => None,
+        }
+    }
+
+    /// The result of the small step taken by the last call to `interp_step`,
+    /// if it took one.
+    pub fn last_step(&self) -> Option<Result<SmallStepRule, ResolvedEvalError>> {
+        self.last_step
+            .as_ref()
+            .map(|result| result.resolve(&self.ctx.interner))
+    }
+
+    /// Whether the input to the last call to `interp_start` failed to parse.
+    pub fn parse_failed(&self) -> bool {
+        self.parse_failed
+    }
+
     fn add_missing_stack_contexts(&mut self, e: Expr) -> Expr {
         match &e {
             Expr::StackContext(_si, ei) => match &(**ei) {
@@ -84,41 +386,613 @@ impl Interp {
         }
     }
 
-    pub fn interp_start(&mut self, input: &str, w: &mut dyn io::Write) -> io::Result<()> {
-        match InterpCommandParser::new().parse(&mut self.ctx.interner, input) {
+    /// Start tracing the evaluation of `input`, printing only the steps
+    /// selected by `options`. This is equivalent to `:trace` with flags.
+    pub fn trace_start(
+        &mut self,
+        options: TraceOptions,
+        input: &str,
+        w: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        self.last_step = None;
+        self.tracer = Tracer::default();
+        if let Some(debugger) = &mut self.debugger {
+            debugger.stale = true;
+        }
+        match ExprParser::new().parse(&mut self.ctx.interner, input) {
             Err(err) => {
                 // TODO: better error messages
                 w.write_fmt(format_args!("{:?}\n", err))?;
             }
-            Ok(InterpCommand::Eval(term_defs, e)) => {
-                for term_def in term_defs {
-                    let name = term_def.0.resolve(&self.ctx.interner);
+            Ok(e) => self.trace_expr(options, e, w)?,
+        }
+        w.flush()
+    }
+
+    fn trace_expr(
+        &mut self,
+        options: TraceOptions,
+        e: Expr,
+        w: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        if e != Expr::default() {
+            let e = self.add_missing_stack_contexts(e);
+            self.warn_every_step("tracing", w)?;
+            w.write_fmt(format_args!(
+                "{}    {}\n",
+                self.show(&self.vms),
+                e.resolve(&self.ctx.interner)
+            ))?;
+            self.tracer = Tracer::new(options.clone(), &mut self.ctx.interner);
+            self.start_measuring(&e);
+            self.command = Some(InterpCommand::Trace(options, e));
+        }
+        Ok(())
+    }
+
+    /// Record every small step taken by evaluations in `sink`, or stop
+    /// recording if it is `None`. Returns the previous sink.
+    pub fn set_json_trace(&mut self, sink: Option<JsonTraceSink>) -> Option<JsonTraceSink> {
+        std::mem::replace(&mut self.json_trace, sink)
+    }
+
+    /// Statistics about the last evaluation, if they were collected.
+    pub fn stats(&self) -> Option<StatsReport> {
+        self.last_stats
+            .as_ref()
+            .map(|stats| stats.resolve(&self.ctx.interner))
+    }
+
+    /// Profile every evaluation, or stop profiling them.
+    pub fn set_profiling(&mut self, on: bool) {
+        self.collect_profile = on;
+    }
+
+    /// The profile of the last evaluation, if it was profiled.
+    pub fn profile(&self) -> Option<Profile> {
+        self.last_profile
+            .as_ref()
+            .map(|profiler| profiler.resolve(&self.ctx.interner))
+    }
+
+    /// Start collecting statistics and profiling, as requested, for an
+    /// evaluation of `e`.
+    fn start_measuring(&mut self, e: &Expr) {
+        if self.collect_stats || self.report_stats {
+            self.stats = Some(Stats::start(&self.vms, e));
+        }
+        if self.collect_profile || self.report_profile {
+            self.profiler = Some(Profiler::default());
+        }
+    }
+
+    fn is_measuring(&self) -> bool {
+        self.stats.is_some() || self.profiler.is_some()
+    }
+
+    /// Record a small step of the current evaluation, which applied `rule`
+    /// and turned the expression into `e`.
+    fn record_step(&mut self, callee: Option<Callee>, rule: SmallStepRule, e: &Expr) {
+        if let Some(stats) = &mut self.stats {
+            stats.record(callee, rule, &self.vms, e);
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(callee, rule, e);
+        }
+    }
+
+    /// Record a call to `sym` in the current evaluation that was skipped,
+    /// saving `steps_saved` steps if that is known, and left the expression
+    /// `e`.
+    fn record_skip(&mut self, sym: TermSymbol, skip: Skip, steps_saved: Option<usize>, e: &Expr) {
+        if let Some(stats) = &mut self.stats {
+            stats.record_skip(sym, skip, steps_saved, &self.vms, e);
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record_skip(sym, skip, steps_saved, e);
+        }
+    }
+
+    /// Stop collecting statistics and profiling, because the current
+    /// evaluation ended.
+    fn finish_measuring(&mut self, w: &mut dyn io::Write) -> io::Result<()> {
+        if let Some(mut stats) = self.stats.take() {
+            stats.finish();
+            if self.report_stats {
+                self.report_stats = false;
+                w.write_fmt(format_args!("{}", stats.resolve(&self.ctx.interner)))?;
+            }
+            self.last_stats = Some(stats);
+        }
+        if let Some(profiler) = self.profiler.take() {
+            if self.report_profile {
+                self.report_profile = false;
+                w.write_fmt(format_args!("{}", profiler.resolve(&self.ctx.interner)))?;
+            }
+            self.last_profile = Some(profiler);
+        }
+        Ok(())
+    }
+
+    /// Start evaluating `e`, if it is not empty.
+    fn eval_expr(&mut self, e: Expr, w: &mut dyn io::Write) -> io::Result<()> {
+        if e != Expr::default() {
+            let mut e = self.add_missing_stack_contexts(e);
+            e.deshadow();
+            if self.json_trace.is_some() {
+                self.warn_every_step("writing a JSON trace", w)?;
+            }
+            w.write_fmt(format_args!(
+                "{}    {}\n",
+                self.show(&self.vms),
+                e.resolve(&self.ctx.interner)
+            ))?;
+            self.start_measuring(&e);
+            if let Some(memo) = &mut self.memo {
+                memo.start();
+            }
+            self.command = Some(InterpCommand::Eval(vec![], e));
+        }
+        Ok(())
+    }
+
+    /// Warn that memoized calls and jets, if they are on, are not used
+    /// while `doing` something that takes every step.
+    fn warn_every_step(&self, doing: &str, w: &mut dyn io::Write) -> io::Result<()> {
+        let unused = match (self.memo.is_some(), self.jets.is_some()) {
+            (true, true) => "Memoized calls and jets are",
+            (true, false) => "Memoized calls are",
+            (false, true) => "Jets are",
+            (false, false) => return Ok(()),
+        };
+        w.write_fmt(format_args!("{} not used while {}.\n", unused, doing))
+    }
+
+    /// Forget the memoized calls and which jets apply, because the
+    /// definitions changed.
+    fn definitions_changed(&mut self) {
+        if let Some(memo) = &mut self.memo {
+            memo.forget();
+        }
+        if let Some(jets) = &mut self.jets {
+            jets.forget();
+        }
+    }
+
+    /// Restore the original definitions of the optimized terms that `sym`
+    /// was inlined into, now that it was redefined, and of the terms those
+    /// were inlined into in turn.
+    fn restore_optimized(&mut self, sym: TermSymbol, w: &mut dyn io::Write) -> io::Result<()> {
+        // `sym` itself is no longer optimized.
+        self.optimized.retain(|(optimized, _, _)| *optimized != sym);
+        let mut redefined = vec![sym];
+        while let Some(sym) = redefined.pop() {
+            while let Some(i) = self
+                .optimized
+                .iter()
+                .position(|(_, _, inlined)| inlined.contains(&sym))
+            {
+                let (optimized, original, _) = self.optimized.remove(i);
+                self.definitions_changed();
+                self.ctx.terms.insert(optimized, original);
+                w.write_fmt(format_args!(
+                    "Restored the original definition of `{}`, since `{}` was redefined.\n",
+                    optimized.resolve(&self.ctx.interner),
+                    sym.resolve(&self.ctx.interner)
+                ))?;
+                redefined.push(optimized);
+            }
+        }
+        Ok(())
+    }
+
+    /// Call `sym` on a copy of the current stacks, and return the values it
+    /// leaves and the number of steps it takes, or `None` if it fails or
+    /// does not finish.
+    fn sample_call(&mut self, sym: TermSymbol) -> Option<(ValueMultistack, usize)> {
+        let mut vms = self.vms.clone();
+        let mut e = self.add_missing_stack_contexts(Expr::Call(sym));
+        e.deshadow();
+        let mut steps = 0;
+        while e != Expr::default() {
+            if steps == DEFAULT_MAX_STEPS || self.ctx.small_step(&mut vms, &mut e).is_err() {
+                return None;
+            }
+            steps += 1;
+        }
+        Some((vms, steps))
+    }
+
+    /// Add `test`, replacing any test with the same name. Returns true if it
+    /// replaced one.
+    fn define_test(&mut self, test: TestDef) -> bool {
+        match self.tests.iter_mut().find(|t| t.name == test.name) {
+            Some(t) => {
+                *t = test;
+                true
+            }
+            None => {
+                self.tests.push(test);
+                false
+            }
+        }
+    }
+
+    /// Make each of `defs` in order, and then evaluate `e`. A law is only
+    /// defined once it is checked, so its check becomes the current command,
+    /// which makes the rest.
+    fn define_and_eval(
+        &mut self,
+        defs: Vec<Definition>,
+        e: Expr,
+        w: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        let mut defs = defs.into_iter();
+        for def in defs.by_ref() {
+            match def {
+                Definition::Term(term_def) => {
+                    let sym = term_def.0;
+                    let name = sym.resolve(&self.ctx.interner);
+                    self.definitions_changed();
                     if let Some(_) = self.ctx.define_term(term_def) {
                         w.write_fmt(format_args!("Redefined `{}`.\n", name))?;
+                        self.restore_optimized(sym, w)?;
                     } else {
                         w.write_fmt(format_args!("Defined `{}`.\n", name))?;
                     }
                 }
+                Definition::Test(test) => {
+                    let name = test.name.clone();
+                    if self.define_test(test) {
+                        w.write_fmt(format_args!("Redefined test `{}`.\n", name))?;
+                    } else {
+                        w.write_fmt(format_args!("Defined test `{}`.\n", name))?;
+                    }
+                }
+                Definition::Law(law) => {
+                    // The law is defined once it is checked, and the remaining
+                    // definitions follow.
+                    let mut lhs = self.add_missing_stack_contexts(law.lhs.clone());
+                    lhs.deshadow();
+                    let mut rhs = self.add_missing_stack_contexts(law.rhs.clone());
+                    rhs.deshadow();
+                    let comparison = Comparison::new(&mut self.ctx, lhs, rhs);
+                    self.command =
+                        Some(InterpCommand::CheckLaw(comparison, law, defs.collect(), e));
+                    return Ok(());
+                }
+            }
+        }
+        self.eval_expr(e, w)
+    }
+
+    fn define_law(&mut self, law: LawDef, w: &mut dyn io::Write) -> io::Result<()> {
+        match self.laws.iter_mut().find(|l| l.name == law.name) {
+            Some(l) => {
+                w.write_fmt(format_args!("Redefined law `{}`.\n", law.name))?;
+                *l = law;
+            }
+            None => {
+                w.write_fmt(format_args!("Defined law `{}`.\n", law.name))?;
+                self.laws.push(law);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the defined tests in the order they were defined, reporting
+    /// whether each passed, and why it failed if it did not. Returns true if
+    /// they all passed.
+    pub fn run_tests(&mut self, w: &mut dyn io::Write) -> io::Result<bool> {
+        let mut failed = 0;
+        for test in self.tests.clone() {
+            w.write_fmt(format_args!("test {} ... ", test.name))?;
+            let mut e = self.add_missing_stack_contexts(test.e.clone());
+            e.deshadow();
+            let outcome = test.run(&mut self.ctx, e);
+            match outcome {
+                TestOutcome::Passed { steps } => {
+                    w.write_fmt(format_args!("ok ({} steps)\n", steps))?;
+                }
+                _ => {
+                    w.write_fmt(format_args!("FAILED\n"))?;
+                    outcome.write_failure(&test, &self.ctx.interner, self.ascii, w)?;
+                    failed += 1;
+                }
+            }
+        }
+        w.write_fmt(format_args!(
+            "{} passed, {} failed.\n",
+            self.tests.len() - failed,
+            failed
+        ))?;
+        Ok(failed == 0)
+    }
+
+    /// The number of steps each defined test takes to pass, or `None` if it
+    /// fails.
+    fn test_steps(&mut self) -> Vec<Option<usize>> {
+        let mut steps = Vec::with_capacity(self.tests.len());
+        for test in self.tests.clone() {
+            let mut e = self.add_missing_stack_contexts(test.e.clone());
+            e.deshadow();
+            steps.push(match test.run(&mut self.ctx, e) {
+                TestOutcome::Passed { steps } => Some(steps),
+                _ => None,
+            });
+        }
+        steps
+    }
+
+    /// The calls made by the last command, if it was a `:trace -tree`.
+    pub fn call_tree(&self) -> Vec<CallNode> {
+        self.tracer.tree(&self.ctx.interner)
+    }
+
+    pub fn interp_start(&mut self, input: &str, w: &mut dyn io::Write) -> io::Result<()> {
+        self.last_step = None;
+        self.tracer = Tracer::default();
+        let command = InterpCommandParser::new().parse(&mut self.ctx.interner, input);
+        self.parse_failed = command.is_err();
+        if let Some(debugger) = &mut self.debugger {
+            match command {
+                Ok(InterpCommand::Eval(..))
+                | Ok(InterpCommand::Trace(..))
+                | Ok(InterpCommand::Time(..))
+                | Ok(InterpCommand::Profile(..))
+                | Ok(InterpCommand::Set(_))
+                | Ok(InterpCommand::Push(..))
+                | Ok(InterpCommand::Pop(_))
+                | Ok(InterpCommand::Drop(_))
+                | Ok(InterpCommand::Optimize(_))
+                | Ok(InterpCommand::Clear) => debugger.stale = true,
+                _ => {}
+            }
+        }
+        match command {
+            Err(err) => {
+                // TODO: better error messages
+                w.write_fmt(format_args!("{:?}\n", err))?;
+            }
+            Ok(InterpCommand::Eval(defs, e)) => self.define_and_eval(defs, e, w)?,
+            Ok(InterpCommand::Trace(options, e)) => self.trace_expr(options, e, w)?,
+            Ok(InterpCommand::Time(e)) => {
+                self.report_stats = true;
+                self.eval_expr(e, w)?;
+                self.report_stats = self.stats.is_some();
+            }
+            Ok(InterpCommand::Profile(e)) => {
+                self.report_profile = true;
+                self.eval_expr(e, w)?;
+                self.report_profile = self.profiler.is_some();
+            }
+            Ok(InterpCommand::Test) => {
+                self.run_tests(w)?;
+            }
+            Ok(InterpCommand::Assert(Judgement::SmallStep(judgement))) => {
+                let mut failure = vec![];
+                if check_small_step(&mut self.ctx, judgement, self.ascii, &mut failure)? {
+                    w.write_fmt(format_args!("Assertion holds.\n"))?;
+                } else {
+                    w.write_fmt(format_args!("Assertion failed.\n"))?;
+                    w.write_all(&failure)?;
+                }
+            }
+            Ok(InterpCommand::Assert(Judgement::BigStep(test))) => {
+                let mut e = self.add_missing_stack_contexts(test.e.clone());
+                e.deshadow();
+                let outcome = test.run(&mut self.ctx, e);
+                if outcome.passed() {
+                    w.write_fmt(format_args!("Assertion holds.\n"))?;
+                } else {
+                    w.write_fmt(format_args!("Assertion failed.\n"))?;
+                    outcome.write_failure(&test, &self.ctx.interner, self.ascii, w)?;
+                }
+            }
+            Ok(InterpCommand::Ascii(ascii)) => {
+                self.ascii = ascii;
+                if ascii {
+                    w.write_fmt(format_args!("Using ASCII notation.\n"))?;
+                } else {
+                    w.write_fmt(format_args!("Using Unicode notation.\n"))?;
+                }
+            }
+            Ok(InterpCommand::Stats(None)) => match &self.last_stats {
+                Some(stats) => {
+                    w.write_fmt(format_args!("{}", stats.resolve(&self.ctx.interner)))?;
+                }
+                None => {
+                    w.write_fmt(format_args!(
+                        "No statistics collected. Use `:stats on` or `:time <expr>`.\n"
+                    ))?;
+                }
+            },
+            Ok(InterpCommand::Stats(Some(on))) => {
+                self.collect_stats = on;
+                if on {
+                    w.write_fmt(format_args!("Collecting statistics.\n"))?;
+                } else {
+                    w.write_fmt(format_args!("Not collecting statistics.\n"))?;
+                }
+            }
+            Ok(InterpCommand::Memo(None)) => match &self.memo {
+                Some(memo) => {
+                    w.write_fmt(format_args!("{}", memo.resolve(&self.ctx.interner)))?;
+                }
+                None => {
+                    w.write_fmt(format_args!("Not memoizing calls. Use `:memo on`.\n"))?;
+                }
+            },
+            Ok(InterpCommand::Memo(Some(on))) => {
+                if on {
+                    if self.memo.is_none() {
+                        self.memo = Some(Memo::default());
+                    }
+                    w.write_fmt(format_args!("Memoizing calls.\n"))?;
+                } else {
+                    self.memo = None;
+                    w.write_fmt(format_args!("Not memoizing calls.\n"))?;
+                }
+            }
+            Ok(InterpCommand::Jets(None)) => match &mut self.jets {
+                Some(jets) => {
+                    w.write_fmt(format_args!("{}", jets.report(&self.ctx)))?;
+                }
+                None => {
+                    w.write_fmt(format_args!(
+                        "Not using jets. Use `:jets on` or `:jets check`.\n"
+                    ))?;
+                }
+            },
+            Ok(InterpCommand::Jets(Some(JetMode::Off))) => {
+                self.jets = None;
+                w.write_fmt(format_args!("Not using jets.\n"))?;
+            }
+            Ok(InterpCommand::Jets(Some(mode))) => {
+                let check = mode == JetMode::Check;
+                match &mut self.jets {
+                    Some(jets) => jets.set_check(check),
+                    None => self.jets = Some(Jets::new(&mut self.ctx.interner, check)),
+                }
+                if check {
+                    w.write_fmt(format_args!(
+                        "Using jets, checked against their definitions.\n"
+                    ))?;
+                } else {
+                    w.write_fmt(format_args!("Using jets.\n"))?;
+                }
+            }
+            Ok(InterpCommand::Debug(e)) => {
                 if e != Expr::default() {
                     let mut e = self.add_missing_stack_contexts(e);
                     e.deshadow();
+                    self.warn_every_step("debugging", w)?;
                     w.write_fmt(format_args!(
                         "{}    {}\n",
-                        self.vms.resolve(&self.ctx.interner),
+                        self.show(&self.vms),
                         e.resolve(&self.ctx.interner)
                     ))?;
-                    self.command = Some(InterpCommand::Eval(vec![], e));
+                    self.debugger = Some(Debugger::new(&self.vms, e));
                 }
             }
-            Ok(InterpCommand::Trace(e)) => {
-                if e != Expr::default() {
-                    let e = self.add_missing_stack_contexts(e);
+            Ok(
+                command @ InterpCommand::Step
+                | command @ InterpCommand::Next
+                | command @ InterpCommand::Finish
+                | command @ InterpCommand::Continue,
+            ) => {
+                if let Some(debugger) = &mut self.debugger {
+                    if debugger.e == Expr::default() {
+                        w.write_fmt(format_args!("Evaluation finished.\n"))?;
+                        return w.flush();
+                    }
+                    let depth = debugger.calls.depth();
+                    debugger.until = match command {
+                        InterpCommand::Step => RunUntil::Step,
+                        InterpCommand::Next => RunUntil::Depth(depth),
+                        InterpCommand::Finish if depth > 0 => RunUntil::Depth(depth - 1),
+                        _ => RunUntil::End,
+                    };
+                    debugger.run_steps = 0;
+                    self.command = Some(command);
+                } else {
+                    w.write_fmt(format_args!("Not debugging.\n"))?;
+                }
+            }
+            Ok(InterpCommand::Where) => {
+                if self.debugger.is_some() {
+                    self.write_where(w)?;
+                } else {
+                    w.write_fmt(format_args!("Not debugging.\n"))?;
+                }
+            }
+            Ok(command @ InterpCommand::Back(_)) | Ok(command @ InterpCommand::Goto(_)) => {
+                if let Some(debugger) = &mut self.debugger {
+                    let target = match command {
+                        InterpCommand::Back(n) => debugger.step.checked_sub(n),
+                        InterpCommand::Goto(n) => Some(n),
+                        _ => unreachable!(),
+                    };
+                    match target {
+                        Some(step) if step <= debugger.step && step >= debugger.first_step() => {
+                            debugger.rewind(&mut self.ctx, &mut self.vms, step);
+                            self.write_where(w)?;
+                        }
+                        Some(step) if step > debugger.step => {
+                            if debugger.e == Expr::default() {
+                                w.write_fmt(format_args!("Evaluation finished.\n"))?;
+                            } else {
+                                debugger.until = RunUntil::At(step);
+                                debugger.run_steps = 0;
+                                self.command = Some(command);
+                            }
+                        }
+                        _ => {
+                            w.write_fmt(format_args!(
+                                "Cannot go back before step {}.\n",
+                                debugger.first_step()
+                            ))?;
+                        }
+                    }
+                } else {
+                    w.write_fmt(format_args!("Not debugging.\n"))?;
+                }
+            }
+            Ok(InterpCommand::Break(None)) => {
+                if self.breakpoints.is_empty() && self.watchpoints.is_empty() {
+                    w.write_fmt(format_args!("No breakpoints or watchpoints.\n"))?;
+                }
+                for sym in self.breakpoints.iter() {
                     w.write_fmt(format_args!(
-                        "{}    {}\n",
-                        self.vms.resolve(&self.ctx.interner),
-                        e.resolve(&self.ctx.interner)
+                        "Breakpoint on `{}`.\n",
+                        sym.resolve(&self.ctx.interner)
                     ))?;
-                    self.command = Some(InterpCommand::Trace(e));
+                }
+                for watchpoint in self.watchpoints.iter() {
+                    let s = watchpoint.stack.resolve(&self.ctx.interner);
+                    if let Some(max_depth) = watchpoint.max_depth {
+                        w.write_fmt(format_args!(
+                            "Watchpoint on `{}` exceeding {} values.\n",
+                            s, max_depth
+                        ))?;
+                    } else {
+                        w.write_fmt(format_args!("Watchpoint on `{}`.\n", s))?;
+                    }
+                }
+            }
+            Ok(InterpCommand::Break(Some(sym))) => {
+                if !self.breakpoints.contains(&sym) {
+                    self.breakpoints.push(sym);
+                }
+                w.write_fmt(format_args!(
+                    "Breakpoint set on `{}`.\n",
+                    sym.resolve(&self.ctx.interner)
+                ))?;
+            }
+            Ok(InterpCommand::Unbreak(sym)) => {
+                let name = sym.resolve(&self.ctx.interner);
+                if self.breakpoints.contains(&sym) {
+                    self.breakpoints.retain(|s| *s != sym);
+                    w.write_fmt(format_args!("Breakpoint removed from `{}`.\n", name))?;
+                } else {
+                    w.write_fmt(format_args!("No breakpoint on `{}`.\n", name))?;
+                }
+            }
+            Ok(InterpCommand::Watch(stack, max_depth)) => {
+                self.watchpoints.retain(|wp| wp.stack != stack);
+                self.watchpoints.push(Watchpoint { stack, max_depth });
+                w.write_fmt(format_args!(
+                    "Watchpoint set on `{}`.\n",
+                    stack.resolve(&self.ctx.interner)
+                ))?;
+            }
+            Ok(InterpCommand::Unwatch(stack)) => {
+                let name = stack.resolve(&self.ctx.interner);
+                if self.watchpoints.iter().any(|wp| wp.stack == stack) {
+                    self.watchpoints.retain(|wp| wp.stack != stack);
+                    w.write_fmt(format_args!("Watchpoint removed from `{}`.\n", name))?;
+                } else {
+                    w.write_fmt(format_args!("No watchpoint on `{}`.\n", name))?;
                 }
             }
             Ok(InterpCommand::Show(sym)) => {
@@ -132,6 +1006,104 @@ impl Interp {
                     w.write_fmt(format_args!("Not defined.\n"))?;
                 }
             }
+            Ok(InterpCommand::Optimize(sym)) => match optimize_term(&self.ctx, sym) {
+                Some(TermDef(_, after)) => {
+                    let name = sym.resolve(&self.ctx.interner);
+                    let before = self.ctx.terms[&sym].clone();
+                    if after == before {
+                        w.write_fmt(format_args!("`{}` is already optimized.\n", name))?;
+                        return w.flush();
+                    }
+                    w.write_fmt(format_args!(
+                        "Before: {{term {} = {}}}\nAfter:  {{term {} = {}}}\n",
+                        name,
+                        before.resolve(&self.ctx.interner),
+                        name,
+                        after.resolve(&self.ctx.interner)
+                    ))?;
+                    let inlined = inlined_terms(&self.ctx, sym);
+                    let call_before = self.sample_call(sym);
+                    let steps_before = self.test_steps();
+                    self.definitions_changed();
+                    // The optimized body is already deshadowed, and
+                    // deshadowing it again could rename its stacks.
+                    self.ctx.terms.insert(sym, after);
+                    let call_after = self.sample_call(sym);
+                    let steps_after = self.test_steps();
+                    let show = |steps: Option<usize>| match steps {
+                        Some(steps) => format!("{} steps", steps),
+                        None => "FAILED".to_owned(),
+                    };
+                    // The call must leave the same values as before.
+                    let call_after = match (&call_before, call_after) {
+                        (Some((before, _)), Some((after, steps))) if *before == after => {
+                            Some(steps)
+                        }
+                        (None, Some((_, steps))) => Some(steps),
+                        _ => None,
+                    };
+                    let call_before = call_before.map(|(_, steps)| steps);
+                    w.write_fmt(format_args!(
+                        "call on the current stacks: {} before, {} after\n",
+                        show(call_before),
+                        show(call_after)
+                    ))?;
+                    let mut regressed = call_before.is_some() && call_after.is_none();
+                    for ((test, before), after) in
+                        self.tests.iter().zip(steps_before).zip(steps_after)
+                    {
+                        w.write_fmt(format_args!(
+                            "test {}: {} before, {} after\n",
+                            test.name,
+                            show(before),
+                            show(after)
+                        ))?;
+                        regressed |= before.is_some() && after.is_none();
+                    }
+                    if regressed {
+                        self.definitions_changed();
+                        self.ctx.terms.insert(sym, before);
+                        w.write_fmt(format_args!(
+                            "Kept the original definition of `{}`.\n",
+                            name
+                        ))?;
+                    } else {
+                        // Keep the first original definition, if it was
+                        // optimized before.
+                        match self.optimized.iter_mut().find(|(s, _, _)| *s == sym) {
+                            Some((_, _, previous)) => {
+                                for s in inlined {
+                                    if !previous.contains(&s) {
+                                        previous.push(s);
+                                    }
+                                }
+                            }
+                            None => self.optimized.push((sym, before, inlined)),
+                        }
+                        w.write_fmt(format_args!("Optimized `{}`.\n", name))?;
+                    }
+                }
+                None => {
+                    w.write_fmt(format_args!("Not defined.\n"))?;
+                }
+            },
+            Ok(InterpCommand::Equiv(e1, e2)) => {
+                let mut e1 = self.add_missing_stack_contexts(e1);
+                e1.deshadow();
+                let mut e2 = self.add_missing_stack_contexts(e2);
+                e2.deshadow();
+                let comparison = Comparison::new(&mut self.ctx, e1, e2);
+                self.command = Some(InterpCommand::CheckEquiv(comparison));
+            }
+            Ok(InterpCommand::Prove(e1, e2)) => {
+                // As for `:equiv`.
+                let mut e1 = self.add_missing_stack_contexts(e1);
+                e1.deshadow();
+                let mut e2 = self.add_missing_stack_contexts(e2);
+                e2.deshadow();
+                let search = ProofSearch::new(&e1, &e2);
+                self.command = Some(InterpCommand::SearchProof(Box::new(search)));
+            }
             Ok(InterpCommand::List) => {
                 let mut names: Vec<String> = self
                     .ctx
@@ -149,66 +1121,429 @@ impl Interp {
                 }
                 w.write_all("\n".as_bytes())?;
             }
-            Ok(InterpCommand::Drop) => {
+            Ok(InterpCommand::Set(vss)) => {
+                for (s, mut vs) in vss {
+                    deshadow_values(&mut vs.0);
+                    self.vms.insert(s, vs);
+                }
+                self.write_value_multistack(w)?;
+            }
+            Ok(InterpCommand::Push(s, mut values)) => {
+                deshadow_values(&mut values);
+                self.vms.stack_mut(s).0.extend(values);
+                self.write_value_multistack(w)?;
+            }
+            Ok(InterpCommand::Pop(s)) => match self.vms.get(&s) {
+                Some(_) => {
+                    self.vms.stack_mut(s).0.pop();
+                    self.write_value_multistack(w)?;
+                }
+                None => {
+                    let name = s.resolve(&self.ctx.interner);
+                    w.write_fmt(format_args!("Stack `{}` is empty.\n", name))?;
+                }
+            },
+            Ok(InterpCommand::Drop(None)) => {
                 self.vms = ValueMultistack::default();
                 w.write_fmt(format_args!("Values dropped.\n"))?;
             }
+            Ok(InterpCommand::Drop(Some(s))) => {
+                self.vms.remove(&s);
+                let name = s.resolve(&self.ctx.interner);
+                w.write_fmt(format_args!("Stack `{}` dropped.\n", name))?;
+            }
             Ok(InterpCommand::Clear) => {
                 self.ctx.terms.clear();
+                self.definitions_changed();
+                self.tests.clear();
+                self.laws.clear();
+                self.optimized.clear();
                 w.write_fmt(format_args!("Definitions cleared.\n"))?;
             }
             Ok(InterpCommand::Reset) => {
+                let json_trace = self.json_trace.take();
+                let collect_stats = self.collect_stats;
+                let collect_profile = self.collect_profile;
+                let ascii = self.ascii;
+                let extra_help = self.extra_help;
+                let memo = self.memo.is_some();
+                let jets = self.jets.as_ref().map(|jets| jets.check());
                 *self = Self::default();
+                self.json_trace = json_trace;
+                self.collect_stats = collect_stats;
+                self.collect_profile = collect_profile;
+                self.ascii = ascii;
+                self.extra_help = extra_help;
+                if memo {
+                    self.memo = Some(Memo::default());
+                }
+                if let Some(check) = jets {
+                    self.jets = Some(Jets::new(&mut self.ctx.interner, check));
+                }
                 w.write_fmt(format_args!("Reset.\n"))?;
             }
             Ok(InterpCommand::Help) => {
                 w.write_all(HELP.as_bytes())?;
+                w.write_all(self.extra_help.as_bytes())?;
+            }
+            Ok(InterpCommand::CheckEquiv(..))
+            | Ok(InterpCommand::CheckLaw(..))
+            | Ok(InterpCommand::SearchProof(_)) => unreachable!(),
+        }
+        w.flush()
+    }
+
+    /// Cancel the current command, if any, showing the value multistack and
+    /// the residual expression where evaluation stopped. Definitions and
+    /// values are kept.
+    pub fn interrupt(&mut self, w: &mut dyn io::Write) -> io::Result<()> {
+        self.tracer.abandon();
+        self.write_new_calls(w)?;
+        match self.command.take() {
+            Some(InterpCommand::Eval(_, e)) | Some(InterpCommand::Trace(_, e)) => {
+                w.write_fmt(format_args!(
+                    "{} {}    {}\n",
+                    self.notation(BigStepArrow),
+                    self.show(&self.vms),
+                    e.resolve(&self.ctx.interner)
+                ))?;
+                w.write_fmt(format_args!("Interrupted.\n"))?;
+                self.finish_measuring(w)?;
+            }
+            Some(InterpCommand::Step)
+            | Some(InterpCommand::Next)
+            | Some(InterpCommand::Finish)
+            | Some(InterpCommand::Continue)
+            | Some(InterpCommand::Goto(_)) => {
+                // Pause the debugging session instead of ending it.
+                w.write_fmt(format_args!("Interrupted.\n"))?;
+                self.write_debugger_state(w)?;
             }
+            Some(InterpCommand::CheckEquiv(..))
+            | Some(InterpCommand::CheckLaw(..))
+            | Some(InterpCommand::SearchProof(_)) => {
+                w.write_fmt(format_args!("Interrupted.\n"))?;
+            }
+            // No other command is left to finish, so there is nothing to
+            // interrupt.
+            Some(_) | None => {}
         }
         w.flush()
     }
 
+    fn write_new_calls(&mut self, w: &mut dyn io::Write) -> io::Result<()> {
+        let ascii = self.ascii;
+        for call in self.tracer.new_calls(&self.ctx.interner) {
+            let call = Notation { value: call, ascii };
+            w.write_fmt(format_args!("{}\n", call))?;
+        }
+        Ok(())
+    }
+
+    fn write_value_multistack(&self, w: &mut dyn io::Write) -> io::Result<()> {
+        w.write_fmt(format_args!("{}\n", self.show(&self.vms)))
+    }
+
+    fn write_debugger_state(&self, w: &mut dyn io::Write) -> io::Result<()> {
+        let debugger = self.debugger.as_ref().unwrap();
+        w.write_fmt(format_args!(
+            "{}    {}\n",
+            self.show(&self.vms),
+            debugger.e.resolve(&self.ctx.interner)
+        ))
+    }
+
+    fn write_where(&self, w: &mut dyn io::Write) -> io::Result<()> {
+        let debugger = self.debugger.as_ref().unwrap();
+        w.write_fmt(format_args!("At step {}:\n", debugger.step))?;
+        for frame in debugger.calls.frames().iter().rev() {
+            let callee = match frame.callee {
+                Callee::Term(sym) => format!("{}", sym.resolve(&self.ctx.interner)),
+                Callee::Apply => "apply".to_owned(),
+            };
+            w.write_fmt(format_args!(
+                "  in {}, called at step {}\n",
+                callee, frame.step
+            ))?;
+        }
+        self.write_debugger_state(w)
+    }
+
+    /// Take one small step of the debugging session, and pause it if it
+    /// reached a breakpoint, a watchpoint, or the end of the current run.
+    fn debug_step(&mut self, command: InterpCommand, w: &mut dyn io::Write) -> io::Result<()> {
+        let watched: Vec<Option<ValueStack>> = self
+            .watchpoints
+            .iter()
+            .map(|wp| self.vms.get(&wp.stack).cloned())
+            .collect();
+        let debugger = self.debugger.as_mut().unwrap();
+        if debugger.run_steps > 0 {
+            if let Some(Callee::Term(sym)) = next_callee(&debugger.e) {
+                if self.breakpoints.contains(&sym) {
+                    w.write_fmt(format_args!(
+                        "Breakpoint `{}`.\n",
+                        sym.resolve(&self.ctx.interner)
+                    ))?;
+                    return self.write_debugger_state(w);
+                }
+            }
+        }
+        let result = debugger.step(&mut self.ctx, &mut self.vms);
+        self.last_step = Some(result.clone());
+        let rule = match result {
+            Ok(rule) => rule,
+            Err(err) => {
+                // Keep the session, so that it can be rewound to find the
+                // cause of the error.
+                w.write_fmt(format_args!("{}\n", err.resolve(&self.ctx.interner)))?;
+                return self.write_debugger_state(w);
+            }
+        };
+        debugger.run_steps += 1;
+        let debugger = self.debugger.as_ref().unwrap();
+        if debugger.until == RunUntil::Step {
+            w.write_fmt(format_args!(
+                "{} {}    {}\n",
+                self.notation(SmallStepArrow(rule)),
+                self.show(&self.vms),
+                debugger.e.resolve(&self.ctx.interner)
+            ))?;
+        }
+        if debugger.e == Expr::default() {
+            w.write_fmt(format_args!(
+                "{} {}    \n",
+                self.notation(BigStepArrow),
+                self.show(&self.vms)
+            ))?;
+            return Ok(());
+        }
+        let mut pause = match debugger.until {
+            RunUntil::Step => true,
+            RunUntil::Depth(depth) => debugger.calls.depth() <= depth,
+            RunUntil::At(step) => debugger.step >= step,
+            RunUntil::End => false,
+        };
+        let mut stopped = false;
+        for (wp, before) in self.watchpoints.iter().zip(watched.iter()) {
+            let after = self.vms.get(&wp.stack);
+            let name = wp.stack.resolve(&self.ctx.interner);
+            if let Some(max_depth) = wp.max_depth {
+                let len = |vs: Option<&ValueStack>| vs.map_or(0, |vs| vs.0.len());
+                if len(before.as_ref()) <= max_depth && len(after) > max_depth {
+                    w.write_fmt(format_args!(
+                        "Stack `{}` exceeds {} values.\n",
+                        name, max_depth
+                    ))?;
+                    stopped = true;
+                }
+            } else if before.as_ref() != after {
+                w.write_fmt(format_args!("Stack `{}` changed.\n", name))?;
+                stopped = true;
+            }
+        }
+        if stopped {
+            if !pause {
+                self.write_debugger_state(w)?;
+            }
+            pause = true;
+        } else if let RunUntil::At(_) = debugger.until {
+            if pause {
+                self.write_where(w)?;
+            }
+        } else if pause && debugger.until != RunUntil::Step {
+            self.write_debugger_state(w)?;
+        }
+        if !pause {
+            self.command = Some(command);
+        }
+        Ok(())
+    }
+
     pub fn interp_step(&mut self, w: &mut dyn io::Write) -> io::Result<()> {
+        self.last_step = None;
         match self.command.take() {
             Some(InterpCommand::Eval(_, mut e)) => {
                 if e != Expr::default() {
-                    if let Err(err) = self.ctx.small_step(&mut self.vms, &mut e) {
-                        w.write_fmt(format_args!(
-                            "⇓ {}    {}\n",
-                            self.vms.resolve(&self.ctx.interner),
-                            e.resolve(&self.ctx.interner)
-                        ))?;
-                        // TODO: better error messages
-                        w.write_fmt(format_args!("{}\n", err.resolve(&self.ctx.interner)))?;
-                        return w.flush();
+                    let callee = if self.is_measuring() {
+                        next_callee(&e)
+                    } else {
+                        None
+                    };
+                    if let (None, Some(jets)) = (&self.json_trace, &mut self.jets) {
+                        let memo = self.memo.as_mut();
+                        match jets.step(&mut self.ctx, &mut self.vms, &mut e, memo) {
+                            JetOutcome::Applied(sym, steps_saved) => {
+                                self.record_skip(sym, Skip::Jet, steps_saved, &e);
+                                self.command = Some(InterpCommand::Eval(vec![], e));
+                                return w.flush();
+                            }
+                            JetOutcome::Mismatch(sym) => {
+                                w.write_fmt(format_args!(
+                                    "The jet for `{}` disagrees with its definition, \
+                                     so the definition is used.\n",
+                                    sym.resolve(&self.ctx.interner)
+                                ))?;
+                            }
+                            JetOutcome::Skipped => {}
+                        }
+                    }
+                    let result = match (&mut self.json_trace, &mut self.memo) {
+                        (Some(sink), _) => sink.step(&mut self.ctx, &mut self.vms, &mut e)?,
+                        (None, Some(memo)) => {
+                            match memo.step(&mut self.ctx, &mut self.vms, &mut e) {
+                                Ok(MemoStep::Step(rule)) => Ok(rule),
+                                Ok(MemoStep::Hit(sym, steps_saved)) => {
+                                    self.record_skip(sym, Skip::Memo, Some(steps_saved), &e);
+                                    self.command = Some(InterpCommand::Eval(vec![], e));
+                                    return w.flush();
+                                }
+                                Err(err) => Err(err),
+                            }
+                        }
+                        (None, None) => self.ctx.small_step(&mut self.vms, &mut e),
+                    };
+                    self.last_step = Some(result.clone());
+                    match result {
+                        Ok(rule) => self.record_step(callee, rule, &e),
+                        Err(err) => {
+                            w.write_fmt(format_args!(
+                                "{} {}    {}\n",
+                                self.notation(BigStepArrow),
+                                self.show(&self.vms),
+                                e.resolve(&self.ctx.interner)
+                            ))?;
+                            // TODO: better error messages
+                            w.write_fmt(format_args!("{}\n", err.resolve(&self.ctx.interner)))?;
+                            self.finish_measuring(w)?;
+                            return w.flush();
+                        }
                     }
                     self.command = Some(InterpCommand::Eval(vec![], e));
                 } else {
                     w.write_fmt(format_args!(
-                        "⇓ {}    {}\n",
-                        self.vms.resolve(&self.ctx.interner),
+                        "{} {}    {}\n",
+                        self.notation(BigStepArrow),
+                        self.show(&self.vms),
                         e.resolve(&self.ctx.interner)
                     ))?;
+                    self.finish_measuring(w)?;
                 }
             }
-            Some(InterpCommand::Trace(mut e)) => {
+            Some(InterpCommand::Trace(options, mut e)) => {
                 if e != Expr::default() {
-                    let rule = match self.ctx.small_step(&mut self.vms, &mut e) {
-                        Ok(rule) => rule,
+                    let callee = if self.is_measuring() {
+                        next_callee(&e)
+                    } else {
+                        None
+                    };
+                    let result = self.tracer.step(&mut self.ctx, &mut self.vms, &mut e);
+                    self.last_step = Some(result.clone().map(|(rule, _)| rule));
+                    let (rule, shown) = match result {
+                        Ok(step) => step,
                         Err(err) => {
+                            self.tracer.abandon();
+                            self.write_new_calls(w)?;
                             // TODO: better error messages
                             w.write_fmt(format_args!("{}\n", err.resolve(&self.ctx.interner)))?;
+                            self.finish_measuring(w)?;
                             return w.flush();
                         }
                     };
-                    // TODO: show function expansion as equality, not as small step?
+                    self.record_step(callee, rule, &e);
+                    self.write_new_calls(w)?;
+                    if options.tree && e == Expr::default() {
+                        w.write_fmt(format_args!(
+                            "{} {}    \n",
+                            self.notation(BigStepArrow),
+                            self.show(&self.vms)
+                        ))?;
+                    }
+                    if shown {
+                        w.write_fmt(format_args!(
+                            "{} {}    {}\n",
+                            self.notation(SmallStepArrow(rule)),
+                            self.show(&self.vms),
+                            e.resolve(&self.ctx.interner)
+                        ))?;
+                    }
+                    if e == Expr::default() {
+                        self.finish_measuring(w)?;
+                    }
+                    self.command = Some(InterpCommand::Trace(options, e));
+                }
+            }
+            Some(
+                command @ InterpCommand::Step
+                | command @ InterpCommand::Next
+                | command @ InterpCommand::Finish
+                | command @ InterpCommand::Continue
+                | command @ InterpCommand::Goto(_),
+            ) => self.debug_step(command, w)?,
+            Some(InterpCommand::CheckEquiv(mut comparison)) => match comparison.step(&mut self.ctx)
+            {
+                Some(Equivalence::NoDifference {
+                    inputs,
+                    inconclusive,
+                }) => {
                     w.write_fmt(format_args!(
-                        "‒{}⟶ {}    {}\n",
-                        rule,
-                        self.vms.resolve(&self.ctx.interner),
-                        e.resolve(&self.ctx.interner)
+                        "No difference found within bounds ({} inputs, with up to {} values on each stack).\n",
+                        inputs, MAX_DEPTH
                     ))?;
-                    self.command = Some(InterpCommand::Trace(e));
+                    if inconclusive > 0 {
+                        w.write_fmt(format_args!(
+                            "Inconclusive on {} of them, where both sides failed or did not finish.\n",
+                            inconclusive
+                        ))?;
+                    }
+                }
+                Some(Equivalence::Counterexample { input, left, right }) => {
+                    self.write_counterexample(&input, &left, &right, w)?;
+                }
+                None => self.command = Some(InterpCommand::CheckEquiv(comparison)),
+            },
+            Some(InterpCommand::CheckLaw(mut comparison, law, defs, e)) => {
+                match comparison.step(&mut self.ctx) {
+                    Some(Equivalence::NoDifference {
+                        inputs,
+                        inconclusive,
+                    }) => {
+                        if inconclusive == inputs {
+                            w.write_fmt(format_args!(
+                                "Both sides of law `{}` failed or did not finish on every input, so it is unchecked.\n",
+                                law.name
+                            ))?;
+                        }
+                        self.define_law(law, w)?;
+                        self.define_and_eval(defs, e, w)?;
+                    }
+                    Some(Equivalence::Counterexample { input, left, right }) => {
+                        w.write_fmt(format_args!("Law `{}` does not hold.\n", law.name))?;
+                        self.write_counterexample(&input, &left, &right, w)?;
+                        self.define_and_eval(defs, e, w)?;
+                    }
+                    None => self.command = Some(InterpCommand::CheckLaw(comparison, law, defs, e)),
+                }
+            }
+            Some(InterpCommand::SearchProof(mut search)) => {
+                match search.step(&self.ctx, &self.laws) {
+                    Some(Proof::Found(steps)) => {
+                        for (e, justification) in steps {
+                            let e = e.resolve(&self.ctx.interner);
+                            match justification {
+                                Some(j) => w.write_fmt(format_args!("= {}    by {}\n", e, j))?,
+                                None => w.write_fmt(format_args!("  {}\n", e))?,
+                            }
+                        }
+                        w.write_fmt(format_args!("Proved.\n"))?;
+                    }
+                    Some(Proof::NotFound { searched }) => {
+                        w.write_fmt(format_args!(
+                            "No proof found within bounds ({} expressions searched).\n",
+                            searched
+                        ))?;
+                    }
+                    None => self.command = Some(InterpCommand::SearchProof(search)),
                 }
             }
             _ => panic!(),
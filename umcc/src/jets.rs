@@ -0,0 +1,436 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Native implementations of builtin terms.
+//!
+//! A jet replaces a call to a builtin term, like `add`, by computing its
+//! result natively, as long as the values it reads are in the encoding the
+//! term expects. Otherwise, and whenever the term or anything it calls has
+//! been redefined, the call is evaluated as usual. In checking mode, each
+//! call that a jet would replace is also evaluated, and the jet is only used
+//! if both give the same result. Since that evaluation happens within a
+//! single step, it is cut off after `CHECK_MAX_STEPS` steps, and the call is
+//! then evaluated as usual instead.
+//!
+//! Like memoized calls, jets are only used by plain evaluations; traces,
+//! debugging sessions and tests take every step.
+
+use crate::builtin::TERM_DEF_SRCS;
+use crate::core::*;
+use crate::display::*;
+use crate::memo::*;
+use crate::parse::*;
+use std::fmt;
+
+/// The number of steps a definition may take to check a jet against it.
+pub const CHECK_MAX_STEPS: usize = 10_000;
+
+/// How evaluations use jets, as set by `:jets`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JetMode {
+    Off,
+    On,
+    /// Use jets, but only after checking them against their definitions.
+    Check,
+}
+
+/// The symbols of the encodings of natural numbers and booleans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Encodings {
+    zero: TermSymbol,
+    succ: TermSymbol,
+    true_: TermSymbol,
+    false_: TermSymbol,
+}
+
+/// A native implementation of a builtin term that pops `arity` values and
+/// pushes one.
+struct Jet {
+    name: &'static str,
+    /// The terms that the values it reads call when they are applied.
+    encoding: &'static [&'static str],
+    arity: usize,
+    /// Returns `None` if a value is not in the expected encoding.
+    native: fn(&Encodings, &[Value]) -> Option<Value>,
+}
+
+static JETS: [Jet; 5] = [
+    Jet {
+        name: "not",
+        encoding: &["_False", "_True"],
+        arity: 1,
+        native: |enc, vs| Some(enc.encode_bool(!enc.decode_bool(&vs[0])?)),
+    },
+    Jet {
+        name: "or",
+        encoding: &["_False", "_True"],
+        arity: 2,
+        native: |enc, vs| {
+            Some(enc.encode_bool(enc.decode_bool(&vs[0])? | enc.decode_bool(&vs[1])?))
+        },
+    },
+    Jet {
+        name: "and",
+        encoding: &["_False", "_True"],
+        arity: 2,
+        native: |enc, vs| {
+            Some(enc.encode_bool(enc.decode_bool(&vs[0])? & enc.decode_bool(&vs[1])?))
+        },
+    },
+    Jet {
+        name: "add",
+        encoding: &["_Z", "_S"],
+        arity: 2,
+        native: |enc, vs| {
+            let (a, b) = (enc.decode_nat(&vs[0])?, enc.decode_nat(&vs[1])?);
+            Some(enc.encode_nat(a.checked_add(b)?))
+        },
+    },
+    Jet {
+        name: "mul",
+        encoding: &["_Z", "_S"],
+        arity: 2,
+        native: |enc, vs| {
+            let (a, b) = (enc.decode_nat(&vs[0])?, enc.decode_nat(&vs[1])?);
+            Some(enc.encode_nat(a.checked_mul(b)?))
+        },
+    },
+];
+
+impl Encodings {
+    fn decode_bool(&self, v: &Value) -> Option<bool> {
+        match v {
+            Value::Quote(e) => match **e {
+                Expr::Call(sym) if sym == self.true_ => Some(true),
+                Expr::Call(sym) if sym == self.false_ => Some(false),
+                _ => None,
+            },
+            Value::Call(_) => None,
+        }
+    }
+
+    fn encode_bool(&self, b: bool) -> Value {
+        let sym = if b { self.true_ } else { self.false_ };
+        Value::Quote(Box::new(Expr::Call(sym)))
+    }
+
+    /// Decodes `[_Z]`, `[[_Z] _S]`, `[[[_Z] _S] _S]` and so on.
+    fn decode_nat(&self, v: &Value) -> Option<usize> {
+        let mut e = match v {
+            Value::Quote(e) => &**e,
+            Value::Call(_) => return None,
+        };
+        let mut n = 0;
+        loop {
+            match e {
+                Expr::Call(sym) if *sym == self.zero => return Some(n),
+                Expr::Compose(es) if es.len() == 2 && es[1] == Expr::Call(self.succ) => {
+                    match &es[0] {
+                        Expr::Quote(ei) => e = ei,
+                        _ => return None,
+                    }
+                }
+                _ => return None,
+            }
+            n += 1;
+        }
+    }
+
+    fn encode_nat(&self, n: usize) -> Value {
+        let mut e = Expr::Call(self.zero);
+        for _ in 0..n {
+            e = Expr::Compose(vec![Expr::Quote(Box::new(e)), Expr::Call(self.succ)]);
+        }
+        Value::Quote(Box::new(e))
+    }
+}
+
+/// A jet, with the builtin definitions it is only valid for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Installed {
+    sym: TermSymbol,
+    /// The term it implements, the terms that term calls, directly or not,
+    /// and the terms of its encoding, with their builtin definitions.
+    definitions: Vec<(TermSymbol, Expr)>,
+    uses: usize,
+    mismatches: usize,
+    unchecked: usize,
+}
+
+/// What `Jets::step` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum JetOutcome {
+    /// The jet for this term replaced the call at the start of the
+    /// expression. When it was checked, the number of steps the definition
+    /// took is known.
+    Applied(TermSymbol, Option<usize>),
+    /// No jet applies, or it could not be checked within
+    /// `CHECK_MAX_STEPS` steps, so the expression should be evaluated as
+    /// usual.
+    Skipped,
+    /// The jet for this term gave a different result than its definition,
+    /// so the expression should be evaluated as usual.
+    Mismatch(TermSymbol),
+}
+
+/// The jets of the builtin terms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Jets {
+    check: bool,
+    encodings: Encodings,
+    /// In the order of `JETS`.
+    installed: Vec<Installed>,
+    /// For each jet, the first term it depends on that no longer has its
+    /// builtin definition, if any. This is computed when first needed after
+    /// the definitions change.
+    changed: Option<Vec<Option<TermSymbol>>>,
+}
+
+impl Jets {
+    pub(crate) fn new(interner: &mut Interner, check: bool) -> Self {
+        let mut builtins = Map::default();
+        for term_def_src in TERM_DEF_SRCS.iter() {
+            let TermDef(sym, mut e) = TermDefParser::new().parse(interner, term_def_src).unwrap();
+            // As `define_term` does.
+            e.deshadow();
+            builtins.insert(sym, e);
+        }
+        let mut sym = |name: &str| TermSymbol(interner.get_or_intern(name));
+        let encodings = Encodings {
+            zero: sym("_Z"),
+            succ: sym("_S"),
+            true_: sym("_True"),
+            false_: sym("_False"),
+        };
+        let installed = JETS
+            .iter()
+            .map(|jet| {
+                let mut syms = vec![sym(jet.name)];
+                syms.extend(jet.encoding.iter().map(|name| sym(name)));
+                let mut i = 0;
+                while i < syms.len() {
+                    let mut calls = vec![];
+                    collect_calls(&builtins[&syms[i]], &mut calls);
+                    for call in calls {
+                        if !syms.contains(&call) {
+                            syms.push(call);
+                        }
+                    }
+                    i += 1;
+                }
+                Installed {
+                    sym: syms[0],
+                    definitions: syms
+                        .into_iter()
+                        .map(|sym| (sym, builtins[&sym].clone()))
+                        .collect(),
+                    uses: 0,
+                    mismatches: 0,
+                    unchecked: 0,
+                }
+            })
+            .collect();
+        Self {
+            check,
+            encodings,
+            installed,
+            changed: None,
+        }
+    }
+
+    pub(crate) fn check(&self) -> bool {
+        self.check
+    }
+
+    pub(crate) fn set_check(&mut self, check: bool) {
+        self.check = check;
+    }
+
+    /// Check the definitions again before the next use, because they
+    /// changed.
+    pub(crate) fn forget(&mut self) {
+        self.changed = None;
+    }
+
+    fn changed(&mut self, ctx: &Context) -> &[Option<TermSymbol>] {
+        let installed = &self.installed;
+        self.changed.get_or_insert_with(|| {
+            installed
+                .iter()
+                .map(|jet| {
+                    jet.definitions
+                        .iter()
+                        .find(|(sym, e)| ctx.terms.get(sym) != Some(e))
+                        .map(|(sym, _)| *sym)
+                })
+                .collect()
+        })
+    }
+
+    /// If `e` starts with a call that a jet applies to, replace the call by
+    /// the jet's result. `memo` is told about the replaced call, so that
+    /// the calls it is nested in are still memoized correctly.
+    pub(crate) fn step(
+        &mut self,
+        ctx: &mut Context,
+        vms: &mut ValueMultistack,
+        e: &mut Expr,
+        memo: Option<&mut Memo>,
+    ) -> JetOutcome {
+        let (call, sym) = match top_call(e) {
+            Some(call) => call,
+            None => return JetOutcome::Skipped,
+        };
+        let i = match self.installed.iter().position(|jet| jet.sym == sym) {
+            Some(i) => i,
+            None => return JetOutcome::Skipped,
+        };
+        if self.changed(ctx)[i].is_some() {
+            return JetOutcome::Skipped;
+        }
+        let sii = match call {
+            Expr::StackContext(_, ei) => match &**ei {
+                Expr::StackContext(sii, _) => *sii,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        let jet = &JETS[i];
+        let result = match vms.get(&sii) {
+            Some(vs) if vs.0.len() >= jet.arity => {
+                let args = &vs.0[vs.0.len() - jet.arity..];
+                match (jet.native)(&self.encodings, args) {
+                    Some(result) => result,
+                    None => return JetOutcome::Skipped,
+                }
+            }
+            _ => return JetOutcome::Skipped,
+        };
+        let mut steps_saved = None;
+        if self.check {
+            let mut expected_vms = vms.clone();
+            let mut expected_e = call.clone();
+            let mut steps = 0;
+            while expected_e != Expr::default() {
+                if steps == CHECK_MAX_STEPS {
+                    self.installed[i].unchecked += 1;
+                    return JetOutcome::Skipped;
+                }
+                if ctx.small_step(&mut expected_vms, &mut expected_e).is_err() {
+                    break;
+                }
+                steps += 1;
+            }
+            let mut actual_vms = vms.clone();
+            replace_args(&mut actual_vms, sii, jet.arity, result.clone());
+            if expected_e != Expr::default() || expected_vms != actual_vms {
+                self.installed[i].mismatches += 1;
+                return JetOutcome::Mismatch(sym);
+            }
+            steps_saved = Some(steps);
+        }
+        if let Some(memo) = memo {
+            memo.before_skipping(vms, sii, jet.arity);
+            replace_args(vms, sii, jet.arity, result);
+            skip_top_call(e);
+            memo.after_skipping(vms, e);
+        } else {
+            replace_args(vms, sii, jet.arity, result);
+            skip_top_call(e);
+        }
+        self.installed[i].uses += 1;
+        JetOutcome::Applied(sym, steps_saved)
+    }
+}
+
+/// Replace the top `arity` values of `s` with `result`.
+fn replace_args(vms: &mut ValueMultistack, s: StackId, arity: usize, result: Value) {
+    let mut vs = vms.stack_mut(s);
+    let len = vs.0.len() - arity;
+    vs.0.truncate(len);
+    vs.0.push(result);
+}
+
+/// Collect the terms that `e` calls, including in quotes.
+fn collect_calls(e: &Expr, calls: &mut Vec<TermSymbol>) {
+    match e {
+        Expr::Intrinsic(_) => {}
+        Expr::Call(sym) => calls.push(*sym),
+        Expr::Quote(ei) | Expr::StackContext(_, ei) => collect_calls(ei, calls),
+        Expr::Compose(es) => {
+            for ei in es.iter() {
+                collect_calls(ei, calls);
+            }
+        }
+    }
+}
+
+/// The uses of a jet, as shown by `:jets`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JetUse {
+    pub term: ResolvedTermSymbol,
+    /// A term the jet depends on that was redefined, which turns it off.
+    pub changed: Option<ResolvedTermSymbol>,
+    pub uses: usize,
+    /// The number of calls on which it disagreed with its definition.
+    pub mismatches: usize,
+    /// The number of calls on which its definition took too long to check
+    /// it against.
+    pub unchecked: usize,
+}
+
+/// The jets and their uses, as shown by `:jets`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JetReport {
+    pub check: bool,
+    pub jets: Vec<JetUse>,
+}
+
+impl Jets {
+    pub(crate) fn report(&mut self, ctx: &Context) -> JetReport {
+        let changed = self.changed(ctx).to_vec();
+        JetReport {
+            check: self.check,
+            jets: self
+                .installed
+                .iter()
+                .zip(changed)
+                .map(|(jet, changed)| JetUse {
+                    term: jet.sym.resolve(&ctx.interner),
+                    changed: changed.map(|sym| sym.resolve(&ctx.interner)),
+                    uses: jet.uses,
+                    mismatches: jet.mismatches,
+                    unchecked: jet.unchecked,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for JetReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.check {
+            writeln!(f, "Using jets, checked against their definitions.")?;
+        } else {
+            writeln!(f, "Using jets.")?;
+        }
+        for jet in self.jets.iter() {
+            write!(f, "  {:16}", jet.term.to_string())?;
+            match &jet.changed {
+                Some(sym) => write!(f, "off, since `{}` was redefined", sym)?,
+                None => write!(f, "{} uses", jet.uses)?,
+            }
+            if jet.mismatches > 0 {
+                write!(f, ", {} mismatches", jet.mismatches)?;
+            }
+            if jet.unchecked > 0 {
+                write!(f, ", {} too long to check", jet.unchecked)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
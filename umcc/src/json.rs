@@ -0,0 +1,212 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::core::SmallStepRule;
+use crate::display::*;
+use crate::trace::CallNode;
+use std::fmt::{self, Display};
+
+/// Displays the wrapped value as JSON.
+///
+/// Stack identifiers, term symbols, and small-step rules are written as
+/// strings in the same notation used by their `Display` impls. Expressions
+/// and values are objects tagged with a `"type"` field, and value multistacks
+/// are arrays of `{"stack": ..., "values": [...]}` objects, ordered by stack.
+pub struct Json<'a, T: ?Sized>(pub &'a T);
+
+pub trait WriteJson {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+impl<T: WriteJson + ?Sized> fmt::Display for Json<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.write_json(f)
+    }
+}
+
+pub(crate) fn write_json_string(s: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    use std::fmt::Write;
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+fn write_json_array<T: WriteJson>(ts: &[T], f: &mut fmt::Formatter) -> fmt::Result {
+    "[".fmt(f)?;
+    if let Some(t) = ts.first() {
+        t.write_json(f)?;
+    }
+    for t in ts.iter().skip(1) {
+        ",".fmt(f)?;
+        t.write_json(f)?;
+    }
+    "]".fmt(f)
+}
+
+impl<T: WriteJson> WriteJson for [T] {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_json_array(self, f)
+    }
+}
+
+impl<T: WriteJson> WriteJson for Vec<T> {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_json_array(self, f)
+    }
+}
+
+impl<T: WriteJson> WriteJson for Option<T> {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Some(t) => t.write_json(f),
+            None => "null".fmt(f),
+        }
+    }
+}
+
+impl WriteJson for usize {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt(f)
+    }
+}
+
+impl WriteJson for ResolvedTermSymbol {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_json_string(&self.0, f)
+    }
+}
+
+impl WriteJson for ResolvedStackId {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_json_string(&self.to_string(), f)
+    }
+}
+
+impl WriteJson for SmallStepRule {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+impl WriteJson for ResolvedExpr {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolvedExpr::Empty => r#"{"type":"compose","exprs":[]}"#.fmt(f),
+            ResolvedExpr::Intrinsic(i) => write!(f, r#"{{"type":"intrinsic","name":"{}"}}"#, i),
+            ResolvedExpr::Call(sym) => {
+                r#"{"type":"call","name":"#.fmt(f)?;
+                sym.write_json(f)?;
+                "}".fmt(f)
+            }
+            ResolvedExpr::Quote(e) => {
+                r#"{"type":"quote","expr":"#.fmt(f)?;
+                e.write_json(f)?;
+                "}".fmt(f)
+            }
+            ResolvedExpr::Compose(es) => {
+                r#"{"type":"compose","exprs":"#.fmt(f)?;
+                es.write_json(f)?;
+                "}".fmt(f)
+            }
+            ResolvedExpr::StackContext(s, e) => {
+                r#"{"type":"stack_context","stack":"#.fmt(f)?;
+                s.write_json(f)?;
+                r#","expr":"#.fmt(f)?;
+                e.write_json(f)?;
+                "}".fmt(f)
+            }
+        }
+    }
+}
+
+impl WriteJson for ResolvedValue {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolvedValue::Call(sym) => {
+                r#"{"type":"call","name":"#.fmt(f)?;
+                sym.write_json(f)?;
+                "}".fmt(f)
+            }
+            ResolvedValue::Quote(e) => {
+                r#"{"type":"quote","expr":"#.fmt(f)?;
+                e.write_json(f)?;
+                "}".fmt(f)
+            }
+        }
+    }
+}
+
+impl WriteJson for ResolvedValueMultistack {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut sids: Vec<&ResolvedStackId> = self.0.keys().collect();
+        sids.sort_unstable();
+        "[".fmt(f)?;
+        for (i, sid) in sids.iter().enumerate() {
+            if i > 0 {
+                ",".fmt(f)?;
+            }
+            r#"{"stack":"#.fmt(f)?;
+            sid.write_json(f)?;
+            r#","values":"#.fmt(f)?;
+            self.0.get(sid).unwrap().0.write_json(f)?;
+            "}".fmt(f)?;
+        }
+        "]".fmt(f)
+    }
+}
+
+impl WriteJson for ResolvedEvalError {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolvedEvalError::EmptyExpr => r#"{"type":"EmptyExpr""#.fmt(f)?,
+            ResolvedEvalError::TooFewValues {
+                available,
+                expected,
+            } => write!(
+                f,
+                r#"{{"type":"TooFewValues","available":{},"expected":{}"#,
+                available, expected
+            )?,
+            ResolvedEvalError::UndefinedTerm(sym) => {
+                r#"{"type":"UndefinedTerm","name":"#.fmt(f)?;
+                sym.write_json(f)?;
+            }
+            ResolvedEvalError::Missing1StackContext => {
+                r#"{"type":"Missing1StackContext""#.fmt(f)?
+            }
+            ResolvedEvalError::Missing2StackContexts => {
+                r#"{"type":"Missing2StackContexts""#.fmt(f)?
+            }
+        }
+        r#","message":"#.fmt(f)?;
+        write_json_string(&self.to_string(), f)?;
+        "}".fmt(f)
+    }
+}
+
+impl WriteJson for CallNode {
+    fn write_json(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        r#"{"expr":"#.fmt(f)?;
+        self.expr.write_json(f)?;
+        r#","before":"#.fmt(f)?;
+        self.before.write_json(f)?;
+        r#","after":"#.fmt(f)?;
+        self.after.write_json(f)?;
+        r#","calls":"#.fmt(f)?;
+        self.calls.write_json(f)?;
+        "}".fmt(f)
+    }
+}
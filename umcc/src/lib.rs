@@ -10,6 +10,14 @@ mod test_core;
 
 pub mod display;
 
+pub mod arena;
+#[cfg(test)]
+mod test_arena;
+
+pub mod json;
+#[cfg(test)]
+mod test_json;
+
 use lalrpop_util::lalrpop_mod;
 lalrpop_mod!(pub parse);
 #[cfg(test)]
@@ -17,6 +25,32 @@ mod test_parse;
 
 mod builtin;
 
+mod debug;
+
+pub mod trace;
+
+pub mod stats;
+
+pub mod memo;
+#[cfg(test)]
+mod test_memo;
+
+pub mod jets;
+#[cfg(test)]
+mod test_jets;
+
+pub mod profile;
+
+pub mod testing;
+
+pub mod optimize;
+#[cfg(test)]
+mod test_optimize;
+
+pub mod equiv;
+
+pub mod prove;
+
 pub mod interp;
 #[cfg(test)]
 mod test_interp;
@@ -0,0 +1,429 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Memoization of term calls.
+//!
+//! Evaluation has no side effects, so a call to a term behaves the same
+//! whenever the values it reads are the same. While a call is evaluated,
+//! each stack it touches is tracked by its low-water mark: the lowest
+//! position the call read, whether by popping, cloning or pushing a value
+//! elsewhere. The values from the mark up to the height of the stack when
+//! the call started are exactly the values the call read, and the values
+//! from the mark up once it returns are exactly the values it left. The
+//! next call with the same stack contexts, made when the same values are on
+//! top of those stacks, is replaced by swapping the one for the other.
+//!
+//! Only calls at the top level of the expression are memoized, since they
+//! have returned as soon as the expression following them is all that is
+//! left. Memoized results depend on the definitions, so they are forgotten
+//! whenever a term is defined. Only plain evaluations use memoized calls;
+//! traces, debugging sessions and tests take every step. The table holds at
+//! most `MEMO_CAPACITY` calls, and the oldest are evicted to make room.
+
+use crate::core::*;
+use crate::debug::redex;
+use crate::display::*;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// The number of calls the table holds before it evicts the oldest.
+pub const MEMO_CAPACITY: usize = 100_000;
+
+/// A memoized call, as the values it read from and left on each stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    /// The values read from each stack it touched, bottom first, and the
+    /// values it left in their place.
+    stacks: Vec<(StackId, Vec<Value>, Vec<Value>)>,
+    /// The number of steps the call took.
+    steps: usize,
+}
+
+/// A call that is being evaluated and recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Recording {
+    call: Expr,
+    /// The number of top-level expressions that followed the call, as in
+    /// `Frame`.
+    rest_len: usize,
+    /// The height of each stack the call touched when the call started,
+    /// and the values below it that the call read, bottom first.
+    stacks: Vec<(StackId, usize, Vec<Value>)>,
+    steps: usize,
+}
+
+impl Recording {
+    /// Record that the next step reads the top `reads` values of `s`, or
+    /// pushes onto it if `reads` is 0.
+    fn touch(&mut self, vms: &ValueMultistack, s: StackId, reads: usize) {
+        let values = vms.get(&s).map_or(&[][..], |vs| &vs.0[..]);
+        let i = match self.stacks.iter().position(|(t, _, _)| *t == s) {
+            Some(i) => i,
+            None => {
+                self.stacks.push((s, values.len(), vec![]));
+                self.stacks.len() - 1
+            }
+        };
+        let (_, start, read) = &mut self.stacks[i];
+        let mark = *start - read.len();
+        let new_mark = values.len().saturating_sub(reads);
+        if new_mark < mark {
+            read.splice(0..0, values[new_mark..mark].iter().cloned());
+        }
+    }
+
+    /// Record the reads of a nested call, which has returned.
+    fn merge(&mut self, nested: &Recording) {
+        for (s, nested_start, nested_read) in nested.stacks.iter() {
+            match self.stacks.iter_mut().find(|(t, _, _)| t == s) {
+                Some((_, start, read)) => {
+                    let mark = *start - read.len();
+                    let nested_mark = nested_start - nested_read.len();
+                    if nested_mark < mark {
+                        let values = &nested_read[..mark - nested_mark];
+                        read.splice(0..0, values.iter().cloned());
+                    }
+                }
+                None => self.stacks.push((*s, *nested_start, nested_read.clone())),
+            }
+        }
+        self.steps += nested.steps;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Counts {
+    hits: usize,
+    misses: usize,
+}
+
+/// What `Memo::step` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MemoStep {
+    /// It took a small step that applied this rule.
+    Step(SmallStepRule),
+    /// It replaced a memoized call to this term by its result, which saved
+    /// this many steps.
+    Hit(TermSymbol, usize),
+}
+
+/// A table of memoized calls, and the calls being recorded for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Memo {
+    table: Map<Expr, Vec<Entry>>,
+    /// The call of each entry in the table, oldest first.
+    order: VecDeque<Expr>,
+    capacity: usize,
+    evicted: usize,
+    recording: Vec<Recording>,
+    counts: Map<TermSymbol, Counts>,
+    steps_saved: usize,
+}
+
+impl Default for Memo {
+    fn default() -> Self {
+        Memo::with_capacity(MEMO_CAPACITY)
+    }
+}
+
+impl Memo {
+    /// A table that holds at most `capacity` calls.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Memo {
+            table: Map::default(),
+            order: VecDeque::new(),
+            capacity,
+            evicted: 0,
+            recording: vec![],
+            counts: Map::default(),
+            steps_saved: 0,
+        }
+    }
+
+    /// Start a new evaluation.
+    pub(crate) fn start(&mut self) {
+        self.recording.clear();
+    }
+
+    /// Forget every memoized call, because the definitions changed.
+    pub(crate) fn forget(&mut self) {
+        self.table.clear();
+        self.order.clear();
+        self.recording.clear();
+    }
+
+    /// Take one small step of `e`, unless it starts with a memoized call,
+    /// in which case the call is replaced by its result.
+    pub(crate) fn step(
+        &mut self,
+        ctx: &mut Context,
+        vms: &mut ValueMultistack,
+        e: &mut Expr,
+    ) -> Result<MemoStep, EvalError> {
+        let call = top_call(e).map(|(call, sym)| (call.clone(), sym));
+        if let Some((call, sym)) = &call {
+            if let Some(entry) = self.lookup(call, vms) {
+                if let Some(recording) = self.recording.last_mut() {
+                    for (s, read, _) in entry.stacks.iter() {
+                        recording.touch(vms, *s, read.len());
+                    }
+                    recording.steps += entry.steps;
+                }
+                for (s, read, left) in entry.stacks.iter() {
+                    let mut vs = vms.stack_mut(*s);
+                    let len = vs.0.len() - read.len();
+                    vs.0.truncate(len);
+                    vs.0.extend(left.iter().cloned());
+                }
+                self.steps_saved += entry.steps;
+                self.counts.entry(*sym).or_default().hits += 1;
+                skip_top_call(e);
+                self.record_returns(vms, e);
+                return Ok(MemoStep::Hit(*sym, entry.steps));
+            }
+        }
+        if let Some(recording) = self.recording.last_mut() {
+            for (s, reads) in stacks_read(redex(e)) {
+                recording.touch(vms, s, reads);
+            }
+        }
+        let rule = match ctx.small_step(vms, e) {
+            Ok(rule) => rule,
+            Err(err) => {
+                self.recording.clear();
+                return Err(err);
+            }
+        };
+        match (call, rule) {
+            (Some((call, sym)), SmallStepRule::LitCall) => {
+                self.counts.entry(sym).or_default().misses += 1;
+                if returns_exactly(e) {
+                    self.recording.push(Recording {
+                        call,
+                        rest_len: top_len(e) - 1,
+                        stacks: vec![],
+                        steps: 1,
+                    });
+                } else {
+                    // The call cannot be recorded, and neither can the
+                    // calls it is nested in.
+                    self.recording.clear();
+                }
+            }
+            _ => {
+                if let Some(recording) = self.recording.last_mut() {
+                    recording.steps += 1;
+                }
+            }
+        }
+        self.record_returns(vms, e);
+        Ok(MemoStep::Step(rule))
+    }
+
+    /// Record that the call at the start of `e` is about to be replaced by
+    /// its result without being evaluated, reading the top `reads` values
+    /// of `s`.
+    pub(crate) fn before_skipping(&mut self, vms: &ValueMultistack, s: StackId, reads: usize) {
+        if let Some(recording) = self.recording.last_mut() {
+            recording.touch(vms, s, reads);
+        }
+    }
+
+    /// Record that the call was replaced, and that `e` is what is left.
+    pub(crate) fn after_skipping(&mut self, vms: &ValueMultistack, e: &Expr) {
+        self.record_returns(vms, e);
+    }
+
+    fn lookup(&self, call: &Expr, vms: &ValueMultistack) -> Option<Entry> {
+        let entries = self.table.get(call)?;
+        entries
+            .iter()
+            .find(|entry| {
+                entry.stacks.iter().all(|(s, read, _)| {
+                    let values = vms.get(s).map_or(&[][..], |vs| &vs.0[..]);
+                    values.len() >= read.len() && values[values.len() - read.len()..] == read[..]
+                })
+            })
+            .cloned()
+    }
+
+    /// Memoize the calls that have returned, now that the expression is
+    /// `e`.
+    fn record_returns(&mut self, vms: &ValueMultistack, e: &Expr) {
+        while let Some(recording) = self.recording.last() {
+            if top_len(e) > recording.rest_len {
+                break;
+            }
+            let recording = self.recording.pop().unwrap();
+            if let Some(caller) = self.recording.last_mut() {
+                caller.merge(&recording);
+            }
+            let stacks = recording
+                .stacks
+                .into_iter()
+                .map(|(s, start, read)| {
+                    let mark = start - read.len();
+                    let values = vms.get(&s).map_or(&[][..], |vs| &vs.0[..]);
+                    (s, read, values[mark..].to_vec())
+                })
+                .collect();
+            if self.capacity == 0 {
+                continue;
+            }
+            if self.order.len() == self.capacity {
+                self.evict();
+            }
+            self.order.push_back(recording.call.clone());
+            self.table.entry(recording.call).or_default().push(Entry {
+                stacks,
+                steps: recording.steps,
+            });
+        }
+    }
+
+    /// Remove the oldest entry from the table. The entries for each call
+    /// are in the order they were added, so it is the first for its call.
+    fn evict(&mut self) {
+        let call = self.order.pop_front().unwrap();
+        let entries = self.table.get_mut(&call).unwrap();
+        entries.remove(0);
+        if entries.is_empty() {
+            self.table.remove(&call);
+        }
+        self.evicted += 1;
+    }
+}
+
+/// If `e` starts with a call at the top level, returns the call and the
+/// term it calls.
+pub(crate) fn top_call(e: &Expr) -> Option<(&Expr, TermSymbol)> {
+    let call = top_redex(e);
+    match call {
+        Expr::StackContext(_, ei) => match &**ei {
+            Expr::StackContext(_, eii) => match &**eii {
+                Expr::Call(sym) => Some((call, *sym)),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Remove the call at the start of `e`, leaving what evaluating the call
+/// would have left once it returned.
+pub(crate) fn skip_top_call(e: &mut Expr) {
+    match e {
+        Expr::Compose(es) if es.len() > 2 => {
+            es.remove(0);
+        }
+        Expr::Compose(es) if es.len() == 2 => *e = es.pop().unwrap(),
+        _ => *e = Expr::default(),
+    }
+}
+
+/// Whether a call at the start of `e` will have returned exactly when no
+/// more than the expressions following it are left at the top level. This
+/// is not the case if a single composition follows it, since the
+/// composition becomes the top level once the call returns.
+fn returns_exactly(e: &Expr) -> bool {
+    match e {
+        Expr::Compose(es) => !(es.len() == 2 && matches!(es[1], Expr::Compose(_))),
+        _ => true,
+    }
+}
+
+/// The first top-level expression of `e`.
+fn top_redex(e: &Expr) -> &Expr {
+    match e {
+        Expr::Compose(es) if !es.is_empty() => &es[0],
+        _ => e,
+    }
+}
+
+/// The number of top-level expressions in `e`.
+fn top_len(e: &Expr) -> usize {
+    match e {
+        Expr::Compose(es) => es.len(),
+        _ => 1,
+    }
+}
+
+/// The stacks that reducing `redex` touches, with the number of values read
+/// from the top of each.
+fn stacks_read(redex: &Expr) -> Vec<(StackId, usize)> {
+    match redex {
+        Expr::StackContext(si, ei) => match &**ei {
+            Expr::StackContext(sii, eii) => match &**eii {
+                Expr::Intrinsic(Intrinsic::Push) => vec![(*si, 1), (*sii, 0)],
+                Expr::Intrinsic(Intrinsic::Pop) => vec![(*sii, 1), (*si, 0)],
+                Expr::Intrinsic(Intrinsic::Compose) => vec![(*sii, 2)],
+                Expr::Intrinsic(_) => vec![(*sii, 1)],
+                Expr::Quote(_) => vec![(*sii, 0)],
+                _ => vec![],
+            },
+            _ => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+/// The hits and misses of memoized calls, as shown by `:memo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoReport {
+    /// The hits and misses of each term, most hits first.
+    pub calls: Vec<(ResolvedTermSymbol, usize, usize)>,
+    /// The number of calls memoized since the definitions last changed.
+    pub entries: usize,
+    /// The number of calls the table holds before it evicts the oldest.
+    pub capacity: usize,
+    /// The number of calls evicted to make room for others.
+    pub evicted: usize,
+    /// The number of steps that hits did not take.
+    pub steps_saved: usize,
+}
+
+impl Resolve for Memo {
+    type Output = MemoReport;
+    fn resolve(&self, interner: &Interner) -> Self::Output {
+        let mut calls: Vec<(ResolvedTermSymbol, usize, usize)> = self
+            .counts
+            .iter()
+            .map(|(sym, counts)| (sym.resolve(interner), counts.hits, counts.misses))
+            .collect();
+        calls.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        MemoReport {
+            calls,
+            entries: self.order.len(),
+            capacity: self.capacity,
+            evicted: self.evicted,
+            steps_saved: self.steps_saved,
+        }
+    }
+}
+
+impl fmt::Display for MemoReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hits: usize = self.calls.iter().map(|(_, hits, _)| hits).sum();
+        let misses: usize = self.calls.iter().map(|(_, _, misses)| misses).sum();
+        writeln!(f, "Calls: {} hits, {} misses", hits, misses)?;
+        for (sym, hits, misses) in self.calls.iter() {
+            writeln!(
+                f,
+                "  {:16}{} hits, {} misses",
+                sym.to_string(),
+                hits,
+                misses
+            )?;
+        }
+        writeln!(
+            f,
+            "Memoized calls: {} (at most {}, {} evicted)",
+            self.entries, self.capacity, self.evicted
+        )?;
+        writeln!(f, "Steps saved: {}", self.steps_saved)
+    }
+}
@@ -0,0 +1,222 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Rewrites of expressions and term definitions that take fewer steps to
+//! evaluate, or give smaller expressions, without changing their results.
+//!
+//! The rewrites are:
+//!
+//! - inlining calls to small, non-recursive terms whose bodies use no stack
+//!   contexts outside of quotes,
+//! - cancelling `push pop`, `pop push` and `clone drop` in the same stack
+//!   contexts, such as `(a|push)(a|pop)`,
+//! - fusing adjacent stack contexts on the same stack, so `(s|e1)(s|e2)`
+//!   becomes `(s|e1 e2)`,
+//! - and dropping empty compositions, including empty stack contexts.
+//!
+//! Quoted expressions are left alone, since they are values that can end up
+//! on a stack. An evaluation that succeeds gives the same result once
+//! optimized, but one that fails for lack of values may fail later, or not
+//! at all, since a cancelled round trip no longer needs the values.
+
+use crate::core::*;
+use crate::stats::expr_size;
+
+/// The largest body, in nodes, of a term that is inlined into its callers.
+pub const MAX_INLINE_SIZE: usize = 8;
+
+/// Optimize `e`, inlining calls to the terms defined in `ctx`.
+pub fn optimize(ctx: &Context, e: &Expr) -> Expr {
+    Optimizer::new(ctx).optimize(e.clone())
+}
+
+/// Optimize the definition of `sym`, or return `None` if it is not defined.
+///
+/// The optimized body is already deshadowed, so it should replace the body
+/// in `ctx.terms` as is, rather than be redefined with `define_term`.
+pub fn optimize_term(ctx: &Context, sym: TermSymbol) -> Option<TermDef> {
+    let e = ctx.terms.get(&sym)?;
+    Some(TermDef(sym, Optimizer::new(ctx).optimize(e.clone())))
+}
+
+/// The terms that optimizing the definition of `sym` inlines, directly or
+/// through other inlined terms. Redefining any of them makes the optimized
+/// definition stale.
+pub fn inlined_terms(ctx: &Context, sym: TermSymbol) -> Vec<TermSymbol> {
+    let mut inlined = vec![];
+    if let Some(e) = ctx.terms.get(&sym) {
+        Optimizer::new(ctx).collect_inlined(e, &mut inlined);
+    }
+    inlined
+}
+
+struct Optimizer {
+    /// The bodies of the terms to inline.
+    inline: Map<TermSymbol, Expr>,
+}
+
+impl Optimizer {
+    fn new(ctx: &Context) -> Self {
+        let inline = ctx
+            .terms
+            .iter()
+            .filter(|(sym, e)| {
+                expr_size(e) <= MAX_INLINE_SIZE
+                    && !has_stack_context(e)
+                    && !is_recursive(ctx, **sym)
+            })
+            .map(|(sym, e)| (*sym, e.clone()))
+            .collect();
+        Optimizer { inline }
+    }
+
+    /// Rewrite `e` until nothing changes. This terminates because only
+    /// non-recursive terms are inlined, and every other rewrite removes
+    /// nodes or stack contexts.
+    fn optimize(&self, mut e: Expr) -> Expr {
+        loop {
+            let new_e = self.rewrite(e.clone());
+            if new_e == e {
+                return e;
+            }
+            e = new_e;
+        }
+    }
+
+    /// Collect the terms that `rewrite` inlines into `e`, on any pass.
+    fn collect_inlined(&self, e: &Expr, inlined: &mut Vec<TermSymbol>) {
+        match e {
+            Expr::Intrinsic(_) | Expr::Quote(_) => {}
+            Expr::Call(sym) => {
+                if let Some(body) = self.inline.get(sym) {
+                    if !inlined.contains(sym) {
+                        inlined.push(*sym);
+                        self.collect_inlined(body, inlined);
+                    }
+                }
+            }
+            Expr::StackContext(_, e) => self.collect_inlined(e, inlined),
+            Expr::Compose(es) => {
+                for e in es {
+                    self.collect_inlined(e, inlined);
+                }
+            }
+        }
+    }
+
+    fn rewrite(&self, e: Expr) -> Expr {
+        match e {
+            Expr::Intrinsic(_) | Expr::Quote(_) => e,
+            Expr::Call(sym) => match self.inline.get(&sym) {
+                // The body has no stack contexts to deshadow, so it can be
+                // used as is.
+                Some(body) => body.clone(),
+                None => e,
+            },
+            Expr::StackContext(s, e) => match self.rewrite(*e) {
+                Expr::Compose(es) if es.is_empty() => Expr::default(),
+                e => Expr::StackContext(s, Box::new(e)),
+            },
+            Expr::Compose(es) => {
+                let mut new_es: Vec<Expr> = Vec::with_capacity(es.len());
+                for e in es {
+                    match self.rewrite(e) {
+                        Expr::Compose(es) => {
+                            for e in es {
+                                push_expr(&mut new_es, e);
+                            }
+                        }
+                        e => push_expr(&mut new_es, e),
+                    }
+                }
+                if new_es.len() == 1 {
+                    new_es.pop().unwrap()
+                } else {
+                    Expr::Compose(new_es)
+                }
+            }
+        }
+    }
+}
+
+/// Push `e` onto the end of a composition, cancelling or fusing it with the
+/// last sub-expression if possible. Fused stack contexts are optimized on
+/// the next pass.
+fn push_expr(es: &mut Vec<Expr>, e: Expr) {
+    match (es.last_mut(), e) {
+        (Some(Expr::Intrinsic(i1)), Expr::Intrinsic(i2)) if cancels(*i1, i2) => {
+            es.pop();
+        }
+        (Some(Expr::StackContext(s1, e1)), Expr::StackContext(s2, e2)) if *s1 == s2 => {
+            let mut fused = into_exprs(std::mem::take(&mut **e1));
+            fused.extend(into_exprs(*e2));
+            **e1 = Expr::Compose(fused);
+        }
+        (_, e) => es.push(e),
+    }
+}
+
+fn into_exprs(e: Expr) -> Vec<Expr> {
+    match e {
+        Expr::Compose(es) => es,
+        e => vec![e],
+    }
+}
+
+/// Returns true if `i1 i2` leaves the stacks as they were.
+fn cancels(i1: Intrinsic, i2: Intrinsic) -> bool {
+    matches!(
+        (i1, i2),
+        (Intrinsic::Push, Intrinsic::Pop)
+            | (Intrinsic::Pop, Intrinsic::Push)
+            | (Intrinsic::Clone, Intrinsic::Drop)
+    )
+}
+
+/// Returns true if `e` has a stack context outside of quotes.
+fn has_stack_context(e: &Expr) -> bool {
+    match e {
+        Expr::Intrinsic(_) | Expr::Call(_) | Expr::Quote(_) => false,
+        Expr::StackContext(..) => true,
+        Expr::Compose(es) => es.iter().any(has_stack_context),
+    }
+}
+
+/// Returns true if `sym` can call itself, directly or through other terms,
+/// including from quotes that might be applied.
+fn is_recursive(ctx: &Context, sym: TermSymbol) -> bool {
+    let mut seen: Vec<TermSymbol> = vec![];
+    let mut pending: Vec<TermSymbol> = vec![sym];
+    while let Some(caller) = pending.pop() {
+        if let Some(e) = ctx.terms.get(&caller) {
+            let mut callees = vec![];
+            collect_calls(e, &mut callees);
+            for callee in callees {
+                if callee == sym {
+                    return true;
+                }
+                if !seen.contains(&callee) {
+                    seen.push(callee);
+                    pending.push(callee);
+                }
+            }
+        }
+    }
+    false
+}
+
+fn collect_calls(e: &Expr, calls: &mut Vec<TermSymbol>) {
+    match e {
+        Expr::Intrinsic(_) => {}
+        Expr::Call(sym) => calls.push(*sym),
+        Expr::Quote(e) | Expr::StackContext(_, e) => collect_calls(e, calls),
+        Expr::Compose(es) => {
+            for e in es {
+                collect_calls(e, calls);
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::core::*;
+use crate::debug::*;
+use crate::display::*;
+use std::fmt;
+use std::io;
+
+/// The name of the frame at the bottom of every stack in a profile.
+const ROOT: &str = "eval";
+
+/// Counts the steps taken under each stack of active calls.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Profiler {
+    step: usize,
+    calls: CallStack,
+    /// The callees of `calls`, outermost first.
+    callees: Vec<Callee>,
+    counts: Map<Vec<Callee>, usize>,
+    /// The calls that were skipped, under each stack of active calls, with
+    /// the steps they saved.
+    skipped: Map<(Vec<Callee>, TermSymbol, Skip), usize>,
+}
+
+impl Profiler {
+    /// Record a small step that applied `rule` and turned the expression
+    /// into `e`. `callee` is the result of `next_callee` before the step.
+    ///
+    /// The step is attributed to the calls that were active before it, so a
+    /// `LitCall` counts against its caller, and the step that returns from a
+    /// call counts against the call.
+    pub(crate) fn record(&mut self, callee: Option<Callee>, rule: SmallStepRule, e: &Expr) {
+        match self.counts.get_mut(&self.callees[..]) {
+            Some(count) => *count += 1,
+            None => {
+                self.counts.insert(self.callees.clone(), 1);
+            }
+        }
+        self.step += 1;
+        self.calls.update(callee, rule, e, self.step);
+        self.callees.clear();
+        self.callees
+            .extend(self.calls.frames().iter().map(|frame| frame.callee));
+    }
+
+    /// Record a call to `sym` that was skipped, saving `steps_saved` steps
+    /// if that is known, and left the expression `e`. It is counted as the
+    /// steps it saved, or as a single step if that is not known, under a
+    /// frame that shows how it was skipped.
+    pub(crate) fn record_skip(
+        &mut self,
+        sym: TermSymbol,
+        skip: Skip,
+        steps_saved: Option<usize>,
+        e: &Expr,
+    ) {
+        let key = (self.callees.clone(), sym, skip);
+        *self.skipped.entry(key).or_default() += steps_saved.unwrap_or(1);
+        self.calls.pop_returned(e);
+        self.callees.clear();
+        self.callees
+            .extend(self.calls.frames().iter().map(|frame| frame.callee));
+    }
+}
+
+/// The number of small steps taken under each stack of active calls, in the
+/// collapsed stack format read by flamegraph tools.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// The frames of each stack, outermost first, along with its count,
+    /// sorted by frames. Calls to terms are named by the term, and calls
+    /// made by `apply` are named `apply`. Skipped calls are named by the
+    /// term followed by `(memoized)` or `(jet)`, and counted as the steps
+    /// they saved, or as one step if that is not known.
+    pub stacks: Vec<(Vec<String>, usize)>,
+}
+
+impl Profile {
+    /// Write the profile in the collapsed stack format, with each stack on
+    /// its own line as its frames separated by `;`, followed by a space and
+    /// the count. Every stack starts with an `eval` frame.
+    pub fn write_collapsed(&self, w: &mut dyn io::Write) -> io::Result<()> {
+        w.write_fmt(format_args!("{}", self))
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (frames, count) in self.stacks.iter() {
+            write!(f, "{}", ROOT)?;
+            for frame in frames.iter() {
+                write!(f, ";{}", frame)?;
+            }
+            writeln!(f, " {}", count)?;
+        }
+        Ok(())
+    }
+}
+
+impl Resolve for Profiler {
+    type Output = Profile;
+    fn resolve(&self, interner: &Interner) -> Self::Output {
+        let frames = |callees: &[Callee]| -> Vec<String> {
+            callees
+                .iter()
+                .map(|callee| match callee {
+                    Callee::Term(sym) => sym.resolve(interner).to_string(),
+                    Callee::Apply => "apply".to_owned(),
+                })
+                .collect()
+        };
+        let mut stacks: Vec<(Vec<String>, usize)> = self
+            .counts
+            .iter()
+            .map(|(callees, count)| (frames(callees), *count))
+            .collect();
+        for ((callees, sym, skip), count) in self.skipped.iter() {
+            let mut frames = frames(callees);
+            let how = match skip {
+                Skip::Memo => "memoized",
+                Skip::Jet => "jet",
+            };
+            frames.push(format!("{} ({})", sym.resolve(interner), how));
+            stacks.push((frames, *count));
+        }
+        stacks.sort_unstable();
+        Profile { stacks }
+    }
+}
@@ -0,0 +1,480 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Equational proofs, found by rewriting both sides of `e1 = e2` until they
+//! meet.
+//!
+//! The rewrites are the rules of the calculus that need no values from the
+//! multistack, read as equations that hold in any context:
+//!
+//! - `f = e`, where `f` is defined as `e` (LitCall),
+//! - `[e] apply = e` (IntrApply), and likewise for `clone`, `drop`, `quote`
+//!   and `compose` applied to quotes,
+//! - `[e] (s|push) = (s|[e])` (IntrPush) and `(s|[e]) (s|pop) = [e]`
+//!   (IntrPop),
+//! - `(s|e1)(s|e2) = (s|e1 e2)` (StkCtxDistr), `(s|) = ` (StkCtxEmpty) and
+//!   `(a|(b|(c|e))) = (b|(c|e))` (StkCtx3Redund),
+//! - and swapping adjacent quotes that are pushed onto different stacks,
+//!   such as `[e1] (s|[e2]) = (s|[e2]) [e1]` (Commute).
+//!
+//! As in evaluation, the `e` of a call or an apply is deshadowed in the
+//! innermost two stack contexts around it.
+//!
+//! Declared laws are also applied, from left to right, to any part of an
+//! expression that matches their left side once its stacks are renamed.
+//! Quotes are never rewritten, since they are values that can be compared.
+//! Both sides are given their stack contexts and deshadowed first, as for
+//! `:equiv`, so the stacks in a proof may be renamed.
+
+use crate::arena::*;
+use crate::core::*;
+use crate::stats::expr_size;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+
+/// The largest number of expressions searched for a proof.
+pub const MAX_PROOF_EXPRS: usize = 20_000;
+
+/// The number of expressions searched by each step of a proof search.
+pub const PROOF_EXPRS_PER_STEP: usize = 100;
+
+/// A law written in the language itself, as `{law name: lhs = rhs}`, which
+/// states that `lhs` can be replaced by `rhs` anywhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LawDef {
+    pub name: String,
+    pub lhs: Expr,
+    pub rhs: Expr,
+}
+
+/// Why one expression in a proof equals the previous one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Justification {
+    Rule(SmallStepRule),
+    Commute,
+    Law(String),
+}
+
+impl fmt::Display for Justification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Justification::Rule(rule) => rule.fmt(f),
+            Justification::Commute => "Commute".fmt(f),
+            Justification::Law(name) => write!(f, "law `{}`", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Proof {
+    /// The chain of equal expressions from `e1` to `e2`, each justified
+    /// except the first.
+    Found(Vec<(Expr, Option<Justification>)>),
+    NotFound {
+        searched: usize,
+    },
+}
+
+/// A search for a proof that `e1 = e2`, trying the smallest expressions
+/// first. Each call to `step` rewrites up to `PROOF_EXPRS_PER_STEP`
+/// expressions, so that a long search can be interrupted.
+#[derive(Debug, Clone)]
+pub(crate) struct ProofSearch {
+    e1: Expr,
+    max_size: usize,
+    // Both sides share the arena, so an expression reached from both has
+    // the same handle.
+    arena: ExprArena,
+    sides: [Side; 2],
+    searched: usize,
+}
+
+impl ProofSearch {
+    pub(crate) fn new(e1: &Expr, e2: &Expr) -> Self {
+        let mut arena = ExprArena::default();
+        let sides = [Side::new(&mut arena, e1), Side::new(&mut arena, e2)];
+        ProofSearch {
+            e1: e1.clone(),
+            max_size: 4 * expr_size(e1).max(expr_size(e2)) + 32,
+            arena,
+            sides,
+            searched: 0,
+        }
+    }
+
+    /// Continue the search, and return the result once there is one.
+    pub(crate) fn step(&mut self, ctx: &Context, laws: &[LawDef]) -> Option<Proof> {
+        let rewriter = Rewriter { ctx, laws };
+        let sides = &mut self.sides;
+        if self.searched == 0 && sides[0].exprs[0] == sides[1].exprs[0] {
+            return Some(Proof::Found(vec![(self.e1.clone(), None)]));
+        }
+        let end = (self.searched + PROOF_EXPRS_PER_STEP).min(MAX_PROOF_EXPRS);
+        while self.searched < end {
+            // Expand the side whose next expression is smaller.
+            let i = match (sides[0].queue.peek(), sides[1].queue.peek()) {
+                (None, None) => break,
+                (Some(_), None) => 0,
+                (None, Some(_)) => 1,
+                (Some(a), Some(b)) => {
+                    if a >= b {
+                        0
+                    } else {
+                        1
+                    }
+                }
+            };
+            let Reverse((_, depth, node)) = sides[i].queue.pop().unwrap();
+            self.searched += 1;
+            let mut rewrites = vec![];
+            rewriter.rewrites(
+                &self.arena.expr(sides[i].exprs[node]),
+                &mut vec![],
+                &mut rewrites,
+            );
+            for (e, justification) in rewrites {
+                let size = expr_size(&e);
+                if size > self.max_size {
+                    continue;
+                }
+                let e = self.arena.intern(&e);
+                if sides[i].index.contains_key(&e) {
+                    continue;
+                }
+                let meets = sides[1 - i].index.get(&e).copied();
+                let child = sides[i].push(e, Some((node, justification)));
+                sides[i].queue.push(Reverse((size, depth + 1, child)));
+                if let Some(other) = meets {
+                    let (left, right) = if i == 0 {
+                        (child, other)
+                    } else {
+                        (other, child)
+                    };
+                    return Some(Proof::Found(chain(&self.arena, sides, left, right)));
+                }
+            }
+        }
+        if self.searched == MAX_PROOF_EXPRS
+            || (sides[0].queue.is_empty() && sides[1].queue.is_empty())
+        {
+            return Some(Proof::NotFound {
+                searched: self.searched,
+            });
+        }
+        None
+    }
+}
+
+/// The expressions reached from one side of the equation.
+#[derive(Debug, Clone)]
+struct Side {
+    exprs: Vec<ExprRef>,
+    index: Map<ExprRef, usize>,
+    /// The expression each one was rewritten from, and how.
+    parents: Vec<Option<(usize, Justification)>>,
+    /// The expressions to rewrite next, by size, depth and node.
+    queue: BinaryHeap<Reverse<(usize, usize, usize)>>,
+}
+
+impl Side {
+    fn new(arena: &mut ExprArena, e: &Expr) -> Self {
+        let mut side = Side {
+            exprs: vec![],
+            index: Map::default(),
+            parents: vec![],
+            queue: BinaryHeap::new(),
+        };
+        let size = expr_size(e);
+        let node = side.push(arena.intern(e), None);
+        side.queue.push(Reverse((size, 0, node)));
+        side
+    }
+
+    fn push(&mut self, e: ExprRef, parent: Option<(usize, Justification)>) -> usize {
+        let node = self.exprs.len();
+        self.index.insert(e, node);
+        self.exprs.push(e);
+        self.parents.push(parent);
+        node
+    }
+}
+
+/// The chain from `e1` to `left`, which is the same expression as `right`,
+/// and back from `right` to `e2`.
+fn chain(
+    arena: &ExprArena,
+    sides: &[Side; 2],
+    left: usize,
+    right: usize,
+) -> Vec<(Expr, Option<Justification>)> {
+    let mut steps = vec![];
+    let mut node = left;
+    // Each expression is justified by the rewrite that reached it.
+    while let Some((parent, j)) = sides[0].parents[node].clone() {
+        steps.push((arena.expr(sides[0].exprs[node]), Some(j)));
+        node = parent;
+    }
+    steps.push((arena.expr(sides[0].exprs[node]), None));
+    steps.reverse();
+    let mut node = right;
+    while let Some((parent, j)) = sides[1].parents[node].clone() {
+        steps.push((arena.expr(sides[1].exprs[parent]), Some(j)));
+        node = parent;
+    }
+    steps
+}
+
+struct Rewriter<'a> {
+    ctx: &'a Context,
+    laws: &'a [LawDef],
+}
+
+impl<'a> Rewriter<'a> {
+    /// Every expression that `e`, nested in the stack contexts `path`, can be
+    /// rewritten to in one step.
+    fn rewrites(&self, e: &Expr, path: &mut Vec<StackId>, out: &mut Vec<(Expr, Justification)>) {
+        for law in self.laws.iter() {
+            if !matches!(law.lhs, Expr::Compose(_)) {
+                let mut renaming = vec![];
+                if match_expr(&law.lhs, e, &mut renaming) {
+                    out.push((rename(&law.rhs, &renaming), law_justification(law)));
+                }
+            }
+        }
+        match e {
+            Expr::Intrinsic(_) | Expr::Quote(_) => {}
+            Expr::Call(sym) => {
+                if let Some(body) = self.ctx.terms.get(sym) {
+                    out.push((
+                        body.deshadowed_in(&mut redex_symbols(path)),
+                        Justification::Rule(SmallStepRule::LitCall),
+                    ));
+                }
+            }
+            Expr::StackContext(s, ei) => {
+                match &**ei {
+                    Expr::Compose(es) if es.is_empty() => {
+                        out.push((
+                            Expr::default(),
+                            Justification::Rule(SmallStepRule::StkCtxEmpty),
+                        ));
+                    }
+                    Expr::StackContext(_, eii) if matches!(**eii, Expr::StackContext(..)) => {
+                        out.push((
+                            (**ei).clone(),
+                            Justification::Rule(SmallStepRule::StkCtx3Redund),
+                        ));
+                    }
+                    _ => {}
+                }
+                let mut inner = vec![];
+                path.push(*s);
+                self.rewrites(ei, path, &mut inner);
+                path.pop();
+                for (ei, j) in inner {
+                    out.push((Expr::StackContext(*s, Box::new(ei)), j));
+                }
+            }
+            Expr::Compose(es) => {
+                for i in 0..es.len() {
+                    let mut inner = vec![];
+                    self.rewrites(&es[i], path, &mut inner);
+                    for (e, j) in inner {
+                        out.push((splice(es, i, 1, e), j));
+                    }
+                    for (len, e, j) in self.window_rewrites(&es[i..], path) {
+                        out.push((splice(es, i, len, e), j));
+                    }
+                }
+            }
+        }
+    }
+
+    /// The rewrites of sequences of sub-expressions at the start of `es`,
+    /// which is nested in the stack contexts `path`, along with the length
+    /// of each sequence.
+    fn window_rewrites(&self, es: &[Expr], path: &[StackId]) -> Vec<(usize, Expr, Justification)> {
+        let mut out = vec![];
+        let rule = Justification::Rule;
+        match es {
+            [Expr::Quote(q), Expr::Intrinsic(intr), ..] => match intr {
+                Intrinsic::Apply => out.push((
+                    2,
+                    q.deshadowed_in(&mut redex_symbols(path)),
+                    rule(SmallStepRule::IntrApply),
+                )),
+                Intrinsic::Clone => out.push((
+                    2,
+                    Expr::Compose(vec![es[0].clone(), es[0].clone()]),
+                    rule(SmallStepRule::IntrClone),
+                )),
+                Intrinsic::Drop => out.push((2, Expr::default(), rule(SmallStepRule::IntrDrop))),
+                Intrinsic::Quote => out.push((
+                    2,
+                    Expr::Quote(Box::new(es[0].clone())),
+                    rule(SmallStepRule::IntrQuote),
+                )),
+                _ => {}
+            },
+            [Expr::Quote(q1), Expr::Quote(q2), Expr::Intrinsic(Intrinsic::Compose), ..] => {
+                let mut qs = into_exprs((**q1).clone());
+                qs.extend(into_exprs((**q2).clone()));
+                out.push((
+                    3,
+                    Expr::Quote(Box::new(compose(qs))),
+                    rule(SmallStepRule::IntrCompose),
+                ));
+            }
+            _ => {}
+        }
+        if let [Expr::Quote(_), Expr::StackContext(s, ei), ..] = es {
+            if **ei == Expr::Intrinsic(Intrinsic::Push) {
+                out.push((
+                    2,
+                    Expr::StackContext(*s, Box::new(es[0].clone())),
+                    rule(SmallStepRule::IntrPush),
+                ));
+            }
+        }
+        if let [Expr::StackContext(s1, e1), Expr::StackContext(s2, e2), ..] = es {
+            if s1 == s2 {
+                if let (Expr::Quote(_), Expr::Intrinsic(Intrinsic::Pop)) = (&**e1, &**e2) {
+                    out.push((2, (**e1).clone(), rule(SmallStepRule::IntrPop)));
+                }
+                let mut fused = into_exprs((**e1).clone());
+                fused.extend(into_exprs((**e2).clone()));
+                out.push((
+                    2,
+                    Expr::StackContext(*s1, Box::new(compose(fused))),
+                    rule(SmallStepRule::StkCtxDistr),
+                ));
+            }
+        }
+        if let [e1, e2, ..] = es {
+            let current = path.last().copied();
+            if let (Some(s1), Some(s2)) = (pushed_quote(e1, current), pushed_quote(e2, current)) {
+                if s1 != s2 {
+                    out.push((
+                        2,
+                        Expr::Compose(vec![e2.clone(), e1.clone()]),
+                        Justification::Commute,
+                    ));
+                }
+            }
+        }
+        for law in self.laws.iter() {
+            if let Expr::Compose(lhs) = &law.lhs {
+                if lhs.is_empty() || lhs.len() > es.len() {
+                    continue;
+                }
+                let mut renaming = vec![];
+                if lhs
+                    .iter()
+                    .zip(es.iter())
+                    .all(|(pat, e)| match_expr(pat, e, &mut renaming))
+                {
+                    out.push((
+                        lhs.len(),
+                        rename(&law.rhs, &renaming),
+                        law_justification(law),
+                    ));
+                }
+            }
+        }
+        out
+    }
+}
+
+fn law_justification(law: &LawDef) -> Justification {
+    Justification::Law(law.name.clone())
+}
+
+/// If `e` pushes a quote, the stack it pushes onto, where a bare quote
+/// pushes onto the `current` stack, if that is known.
+fn pushed_quote(e: &Expr, current: Option<StackId>) -> Option<StackId> {
+    match e {
+        Expr::Quote(_) => current,
+        Expr::StackContext(s, ei) if matches!(**ei, Expr::Quote(_)) => Some(*s),
+        _ => None,
+    }
+}
+
+/// The symbols that a call or apply nested in the stack contexts `path` is
+/// deshadowed in, which are those of the innermost two, since the rest are
+/// redundant by the time it is reduced.
+fn redex_symbols(path: &[StackId]) -> Vec<StackSymbol> {
+    path.iter().rev().take(2).map(|s| s.0).collect()
+}
+
+/// Match `e` against the pattern `pat`, extending `renaming` from the
+/// stacks of `pat` to those of `e`, which must stay one to one.
+fn match_expr(pat: &Expr, e: &Expr, renaming: &mut Vec<(StackId, StackId)>) -> bool {
+    match (pat, e) {
+        (Expr::Intrinsic(a), Expr::Intrinsic(b)) => a == b,
+        (Expr::Call(a), Expr::Call(b)) => a == b,
+        (Expr::Quote(a), Expr::Quote(b)) => match_expr(a, b, renaming),
+        (Expr::Compose(a), Expr::Compose(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b.iter())
+                    .all(|(a, b)| match_expr(a, b, renaming))
+        }
+        (Expr::StackContext(s1, a), Expr::StackContext(s2, b)) => {
+            match renaming.iter().find(|(from, to)| from == s1 || to == s2) {
+                Some(pair) if *pair != (*s1, *s2) => return false,
+                Some(_) => {}
+                None => renaming.push((*s1, *s2)),
+            }
+            match_expr(a, b, renaming)
+        }
+        _ => false,
+    }
+}
+
+fn rename(e: &Expr, renaming: &[(StackId, StackId)]) -> Expr {
+    match e {
+        Expr::Intrinsic(_) | Expr::Call(_) => e.clone(),
+        Expr::Quote(e) => Expr::Quote(Box::new(rename(e, renaming))),
+        Expr::Compose(es) => Expr::Compose(es.iter().map(|e| rename(e, renaming)).collect()),
+        Expr::StackContext(s, e) => {
+            let s = match renaming.iter().find(|(from, _)| from == s) {
+                Some((_, to)) => *to,
+                None => *s,
+            };
+            Expr::StackContext(s, Box::new(rename(e, renaming)))
+        }
+    }
+}
+
+/// Replace `len` sub-expressions of `es`, starting at `i`, with `e`.
+fn splice(es: &[Expr], i: usize, len: usize, e: Expr) -> Expr {
+    let mut new_es = Vec::with_capacity(es.len() + 1);
+    new_es.extend(es[..i].iter().cloned());
+    new_es.push(e);
+    new_es.extend(es[i + len..].iter().cloned());
+    compose(new_es)
+}
+
+/// Compose `es`, flattening any compositions among them.
+fn compose(es: Vec<Expr>) -> Expr {
+    let mut new_es = Vec::with_capacity(es.len());
+    for e in es {
+        new_es.extend(into_exprs(e));
+    }
+    if new_es.len() == 1 {
+        new_es.pop().unwrap()
+    } else {
+        Expr::Compose(new_es)
+    }
+}
+
+fn into_exprs(e: Expr) -> Vec<Expr> {
+    match e {
+        Expr::Compose(es) => es,
+        e => vec![e],
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::core::*;
+use crate::debug::{Callee, Skip};
+use crate::display::*;
+use std::fmt;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+const RULES: [SmallStepRule; 12] = [
+    SmallStepRule::IntrPush,
+    SmallStepRule::IntrPop,
+    SmallStepRule::IntrClone,
+    SmallStepRule::IntrDrop,
+    SmallStepRule::IntrQuote,
+    SmallStepRule::IntrCompose,
+    SmallStepRule::IntrApply,
+    SmallStepRule::LitCall,
+    SmallStepRule::LitQuote,
+    SmallStepRule::StkCtxDistr,
+    SmallStepRule::StkCtx3Redund,
+    SmallStepRule::StkCtxEmpty,
+];
+
+/// Counters collected while evaluating an expression.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Stats {
+    steps: usize,
+    rules: [usize; RULES.len()],
+    calls: Map<TermSymbol, usize>,
+    peak_expr_size: usize,
+    peak_stack_sizes: Map<StackId, usize>,
+    memo_hits: usize,
+    memo_steps_saved: usize,
+    jet_uses: usize,
+    /// `None` once a jet was used without knowing how many steps it saved.
+    jet_steps_saved: Option<usize>,
+    #[cfg(not(target_arch = "wasm32"))]
+    started: Option<Instant>,
+    elapsed: Option<Duration>,
+}
+
+impl Stats {
+    /// Start collecting statistics for the evaluation of `e`.
+    pub(crate) fn start(vms: &ValueMultistack, e: &Expr) -> Self {
+        let mut stats = Self {
+            jet_steps_saved: Some(0),
+            ..Self::default()
+        };
+        stats.record_sizes(vms, e);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            stats.started = Some(Instant::now());
+        }
+        stats
+    }
+
+    /// Record a small step that applied `rule`. `callee` is the result of
+    /// `next_callee` before the step.
+    pub(crate) fn record(
+        &mut self,
+        callee: Option<Callee>,
+        rule: SmallStepRule,
+        vms: &ValueMultistack,
+        e: &Expr,
+    ) {
+        self.steps += 1;
+        self.rules[rule as usize] += 1;
+        if let (SmallStepRule::LitCall, Some(Callee::Term(sym))) = (rule, callee) {
+            *self.calls.entry(sym).or_default() += 1;
+        }
+        self.record_sizes(vms, e);
+    }
+
+    /// Record a call to `sym` that was skipped, saving `steps_saved` steps
+    /// if that is known, and left the expression `e`.
+    pub(crate) fn record_skip(
+        &mut self,
+        sym: TermSymbol,
+        skip: Skip,
+        steps_saved: Option<usize>,
+        vms: &ValueMultistack,
+        e: &Expr,
+    ) {
+        *self.calls.entry(sym).or_default() += 1;
+        match skip {
+            Skip::Memo => {
+                self.memo_hits += 1;
+                self.memo_steps_saved += steps_saved.unwrap_or(0);
+            }
+            Skip::Jet => {
+                self.jet_uses += 1;
+                self.jet_steps_saved = self
+                    .jet_steps_saved
+                    .and_then(|saved| Some(saved + steps_saved?));
+            }
+        }
+        self.record_sizes(vms, e);
+    }
+
+    fn record_sizes(&mut self, vms: &ValueMultistack, e: &Expr) {
+        self.peak_expr_size = self.peak_expr_size.max(expr_size(e));
+        for (s, vs) in vms.iter() {
+            let peak = self.peak_stack_sizes.entry(s).or_default();
+            *peak = (*peak).max(vs.0.len());
+        }
+    }
+
+    /// Stop the clock, when evaluation is finished.
+    pub(crate) fn finish(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(started) = self.started.take() {
+            self.elapsed = Some(started.elapsed());
+        }
+    }
+}
+
+/// The number of nodes in `e`.
+pub(crate) fn expr_size(e: &Expr) -> usize {
+    match e {
+        Expr::Intrinsic(_) | Expr::Call(_) => 1,
+        Expr::Quote(e) | Expr::StackContext(_, e) => 1 + expr_size(e),
+        Expr::Compose(es) => 1 + es.iter().map(expr_size).sum::<usize>(),
+    }
+}
+
+/// Statistics about an evaluation, as shown by `:stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsReport {
+    /// The total number of small steps taken.
+    pub steps: usize,
+    /// The number of steps that applied each rule, leaving out unused rules.
+    pub rules: Vec<(SmallStepRule, usize)>,
+    /// The number of times each term was called, most called first,
+    /// including the calls that were skipped.
+    pub calls: Vec<(ResolvedTermSymbol, usize)>,
+    /// The number of calls whose memoized results were used, and the
+    /// number of steps they did not take.
+    pub memo_hits: usize,
+    pub memo_steps_saved: usize,
+    /// The number of calls that jets computed, and the number of steps they
+    /// did not take, if that is known. It is only known when jets are
+    /// checked against their definitions.
+    pub jet_uses: usize,
+    pub jet_steps_saved: Option<usize>,
+    /// The largest number of nodes in the expression being evaluated.
+    pub peak_expr_size: usize,
+    /// The largest number of values held by each stack, ordered by stack.
+    pub peak_stack_sizes: Vec<(ResolvedStackId, usize)>,
+    /// The time taken, if the evaluation finished and it could be measured.
+    pub elapsed: Option<Duration>,
+}
+
+impl Resolve for Stats {
+    type Output = StatsReport;
+    fn resolve(&self, interner: &Interner) -> Self::Output {
+        let rules = RULES
+            .iter()
+            .map(|&rule| (rule, self.rules[rule as usize]))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        let mut calls: Vec<(ResolvedTermSymbol, usize)> = self
+            .calls
+            .iter()
+            .map(|(sym, count)| (sym.resolve(interner), *count))
+            .collect();
+        calls.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let mut peak_stack_sizes: Vec<(ResolvedStackId, usize)> = self
+            .peak_stack_sizes
+            .iter()
+            .map(|(s, size)| (s.resolve(interner), *size))
+            .collect();
+        peak_stack_sizes.sort_unstable();
+        StatsReport {
+            steps: self.steps,
+            rules,
+            calls,
+            memo_hits: self.memo_hits,
+            memo_steps_saved: self.memo_steps_saved,
+            jet_uses: self.jet_uses,
+            jet_steps_saved: self.jet_steps_saved,
+            peak_expr_size: self.peak_expr_size,
+            peak_stack_sizes,
+            elapsed: self.elapsed,
+        }
+    }
+}
+
+impl fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Steps: {}", self.steps)?;
+        for (rule, count) in self.rules.iter() {
+            writeln!(f, "  {:16}{}", rule.to_string(), count)?;
+        }
+        if !self.calls.is_empty() {
+            writeln!(f, "Calls:")?;
+            for (sym, count) in self.calls.iter() {
+                writeln!(f, "  {:16}{}", sym.to_string(), count)?;
+            }
+        }
+        if self.memo_hits > 0 || self.jet_uses > 0 {
+            writeln!(f, "Skipped calls:")?;
+        }
+        if self.memo_hits > 0 {
+            writeln!(
+                f,
+                "  {:16}{} calls, {} steps saved",
+                "memoized", self.memo_hits, self.memo_steps_saved
+            )?;
+        }
+        if self.jet_uses > 0 {
+            write!(f, "  {:16}{} calls", "jets", self.jet_uses)?;
+            if let Some(saved) = self.jet_steps_saved {
+                write!(f, ", {} steps saved", saved)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "Peak expression size: {}", self.peak_expr_size)?;
+        writeln!(
+            f,
+            "Peak stack sizes ({} stacks touched):",
+            self.peak_stack_sizes.len()
+        )?;
+        for (s, size) in self.peak_stack_sizes.iter() {
+            writeln!(f, "  {:16}{}", s.to_string(), size)?;
+        }
+        if let Some(elapsed) = self.elapsed {
+            writeln!(f, "Time: {:?}", elapsed)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::arena::*;
+use crate::core::*;
+use crate::parse::*;
+
+#[test]
+fn test_arena_intern() {
+    let mut ctx = Context::default();
+    let mut arena = ExprArena::default();
+    let srcs = [
+        "",
+        "(s|[x] [y] compose)",
+        "[[a] [a]] [a] clone",
+        "(s|(t|[x] push))",
+        "(s|[x] [y] compose) (s|[x] [y] compose)",
+    ];
+    let mut refs = vec![];
+    for src in srcs.iter() {
+        let e = ExprParser::new().parse(&mut ctx.interner, src).unwrap();
+        let r = arena.intern(&e);
+        assert_eq!(arena.expr(r), e, "interning {}", src);
+        assert_eq!(arena.intern(&e), r, "interning {} again", src);
+        refs.push(r);
+    }
+    for (i, r1) in refs.iter().enumerate() {
+        for (j, r2) in refs.iter().enumerate() {
+            assert_eq!(r1 == r2, i == j);
+        }
+    }
+}
+
+#[test]
+fn test_arena_shares_subtrees() {
+    let mut ctx = Context::default();
+    let mut arena = ExprArena::default();
+    // `[x] [x]` is one `Call`, one `Quote` and one `Compose` of them, and
+    // each quote adds two more nodes.
+    let mut src = String::from("[x] [x]");
+    let mut e = ExprParser::new().parse(&mut ctx.interner, &src).unwrap();
+    arena.intern(&e);
+    assert_eq!(arena.len(), 3);
+    for _ in 0..10 {
+        src = format!("[{}] [{}]", src, src);
+        e = ExprParser::new().parse(&mut ctx.interner, &src).unwrap();
+        arena.intern(&e);
+    }
+    assert_eq!(arena.len(), 3 + 2 * 10);
+    let r = arena.intern(&e);
+    assert_eq!(arena.expr(r), e);
+}
+
+#[test]
+fn test_arena_multistack() {
+    let mut ctx = Context::default();
+    let mut arena = ExprArena::default();
+    let s = StackId(StackSymbol(ctx.interner.get_or_intern_static("s")), 0);
+    let t = StackId(StackSymbol(ctx.interner.get_or_intern_static("t")), 1);
+    let x = TermSymbol(ctx.interner.get_or_intern_static("x"));
+    let quote = ExprParser::new()
+        .parse(&mut ctx.interner, "(s|[x] push)")
+        .unwrap();
+    let mut vms = ValueMultistack::default();
+    vms.insert(
+        s,
+        ValueStack(vec![Value::Call(x), Value::Quote(Box::new(quote.clone()))]),
+    );
+    vms.insert(t, ValueStack(vec![Value::Quote(Box::new(quote))]));
+    let interned = arena.intern_multistack(&vms);
+    assert_eq!(arena.multistack(&interned), vms);
+}
@@ -45,6 +45,173 @@ fn test_expr_deshadow_not_through_quote() {
     assert_eq!(e_deshadowed, e);
 }
 
+#[test]
+fn test_call_and_apply_deshadow_like_the_whole_redex() {
+    let bodies = [
+        "e",
+        "(s|e)",
+        "(t|(s|e) (t|e))",
+        "(s|(s|(s'4|e)))",
+        "(u|(u|e)) (s|[(s|(s|e))])",
+        "[(t|(t|e))] (t|[(t|e)])",
+    ];
+    let redexes = ["(s|(t|x))", "(s|(s'1|x))", "(t|(t|x))", "(u|(v|x))"];
+    for body_src in bodies.iter() {
+        for redex_src in redexes.iter() {
+            let mut ctx = Context::default();
+            let body = ExprParser::new()
+                .parse(&mut ctx.interner, body_src)
+                .unwrap();
+            let redex = ExprParser::new()
+                .parse(&mut ctx.interner, redex_src)
+                .unwrap();
+            let (si, sii) = match &redex {
+                Expr::StackContext(si, ei) => match &**ei {
+                    Expr::StackContext(sii, _) => (*si, *sii),
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            };
+            // Deshadowing the whole redex is the reference.
+            let mut expected = Expr::StackContext(
+                si,
+                Box::new(Expr::StackContext(sii, Box::new(body.clone()))),
+            );
+            expected.deshadow();
+
+            // Define the body directly, since `define_term` deshadows it.
+            let f = TermSymbol(ctx.interner.get_or_intern_static("f"));
+            ctx.terms.insert(f, body.clone());
+            let mut call = Expr::StackContext(
+                si,
+                Box::new(Expr::StackContext(sii, Box::new(Expr::Call(f)))),
+            );
+            let mut vms = ValueMultistack::default();
+            assert_eq!(
+                ctx.small_step(&mut vms, &mut call),
+                Ok(SmallStepRule::LitCall)
+            );
+            assert_eq!(call, expected, "calling {} in {}", body_src, redex_src);
+
+            let mut apply = Expr::StackContext(
+                si,
+                Box::new(Expr::StackContext(
+                    sii,
+                    Box::new(Expr::Intrinsic(Intrinsic::Apply)),
+                )),
+            );
+            let mut vms = ValueMultistack::default();
+            vms.stack_mut(sii)
+                .0
+                .push(Value::Quote(Box::new(body.clone())));
+            assert_eq!(
+                ctx.small_step(&mut vms, &mut apply),
+                Ok(SmallStepRule::IntrApply)
+            );
+            assert_eq!(apply, expected, "applying {} in {}", body_src, redex_src);
+        }
+    }
+}
+
+#[test]
+fn test_value_multistack() {
+    let mut ctx = Context::default();
+    let s = StackSymbol(ctx.interner.get_or_intern_static("s"));
+    let x = Value::Call(TermSymbol(ctx.interner.get_or_intern_static("x")));
+    let ids = [StackId(s, 0), StackId(s, 3), StackId(s, 1_000_000)];
+    let mut vms = ValueMultistack::default();
+    for (i, sid) in ids.iter().enumerate() {
+        vms.stack_mut(*sid).0.extend(vec![x.clone(); i + 1]);
+    }
+    assert_eq!(vms.len(), 3);
+    for (i, sid) in ids.iter().enumerate() {
+        assert_eq!(vms.get(sid), Some(&ValueStack(vec![x.clone(); i + 1])));
+    }
+    // Empty stacks are the same as unused ones.
+    assert_eq!(vms.get(&StackId(s, 1)), None);
+    vms.stack_mut(StackId(s, 2));
+    assert_eq!(vms.len(), 3);
+    // The count follows stacks that become empty or get values.
+    vms.stack_mut(ids[0]).0.pop();
+    assert_eq!(vms.len(), 2);
+    vms.stack_mut(ids[0]).0.push(x.clone());
+    assert_eq!(vms.len(), 3);
+    let other: ValueMultistack = vms.iter().map(|(s, vs)| (s, vs.clone())).collect();
+    assert_eq!(vms, other);
+    for sid in ids.iter() {
+        assert!(vms.remove(sid).is_some());
+        assert_eq!(vms.remove(sid), None);
+    }
+    assert!(vms.is_empty());
+    assert_eq!(vms, ValueMultistack::default());
+}
+
+#[test]
+fn test_expr_canonicalize() {
+    let cases = [
+        // Both contexts name the same stack, so it is not renamed.
+        ("(s|(s|e))", "(s|(s|e))"),
+        ("(s|(s'2|e))", "(s|(s'1|e))"),
+        ("(s|(s'3|a) (s'5|b) (s'3|c))", "(s|(s'1|a) (s'2|b) (s'1|c))"),
+        // `s'1` is free, so the bound stack is renumbered around it.
+        ("(s'1|a) (s|(s'4|b))", "(s'1|a) (s|(s'2|b))"),
+        // A stack that is named outside of its symbol's context is free.
+        ("(s'4|a) (s|(s'4|b))", "(s'4|a) (s|(s'4|b))"),
+        ("(t|(s|(s'7|e)))", "(t|(s|(s'1|e)))"),
+        ("[(s|(s'2|e))] (s|[(s'3|e)])", "[(s|(s'1|e))] (s|[(s'3|e)])"),
+    ];
+    let mut ctx = Context::default();
+    for (src, expected) in cases.iter() {
+        let mut e = ExprParser::new().parse(&mut ctx.interner, src).unwrap();
+        let expected = ExprParser::new()
+            .parse(&mut ctx.interner, expected)
+            .unwrap();
+        e.canonicalize();
+        assert_eq!(e, expected, "canonicalizing {}", src);
+    }
+}
+
+#[test]
+fn test_expr_alpha_eq() {
+    let cases = [
+        ("(s|(s'1|e))", "(s|(s'2|e))", true),
+        ("(s|(s'1|a) (s'2|b))", "(s|(s'2|a) (s'1|b))", true),
+        ("(s|(s'1|a) (s'1|b))", "(s|(s'1|a) (s'2|b))", false),
+        ("(s'1|e)", "(s'2|e)", false),
+        ("(s|(t|e))", "(s|(t'1|e))", false),
+    ];
+    let mut ctx = Context::default();
+    for (src1, src2, expected) in cases.iter() {
+        let e1 = ExprParser::new().parse(&mut ctx.interner, src1).unwrap();
+        let e2 = ExprParser::new().parse(&mut ctx.interner, src2).unwrap();
+        assert_eq!(
+            e1.alpha_eq(&e2),
+            *expected,
+            "comparing {} and {}",
+            src1,
+            src2
+        );
+        assert_eq!(
+            e2.alpha_eq(&e1),
+            *expected,
+            "comparing {} and {}",
+            src2,
+            src1
+        );
+    }
+    // Deshadowing twice gives an expression that is alpha-equivalent to
+    // deshadowing once.
+    let e = ExprParser::new()
+        .parse(&mut ctx.interner, "(s|(s|(s|e)))")
+        .unwrap();
+    let mut once = e.clone();
+    once.deshadow();
+    let mut twice = once.clone();
+    twice.deshadow();
+    assert_ne!(once, twice);
+    assert!(once.alpha_eq(&twice));
+}
+
 #[test]
 fn test_define_term() {
     let mut ctx = Context::default();
@@ -4,7 +4,9 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use crate::interp::{Interp, HELP};
+use crate::core::SmallStepRule;
+use crate::interp::{is_incomplete, Interp, HELP};
+use crate::trace::TraceOptions;
 
 struct TestSession(Vec<TestCommand>);
 
@@ -14,6 +16,16 @@ struct TestCommand {
     step_output: &'static [&'static str],
 }
 
+/// Run `input` to completion, and return everything it wrote.
+fn run(interp: &mut Interp, input: &str) -> String {
+    let mut buffer = Vec::with_capacity(4096);
+    interp.interp_start(input, &mut buffer).unwrap();
+    while !interp.is_done() {
+        interp.interp_step(&mut buffer).unwrap();
+    }
+    String::from_utf8(buffer).unwrap()
+}
+
 #[test]
 fn test_interp() {
     let sessions = [
@@ -339,3 +351,1027 @@ fn test_interp() {
         }
     }
 }
+
+#[test]
+fn test_is_incomplete() {
+    let cases = [
+        ("", false),
+        ("(s|push)", false),
+        ("{term foo =", true),
+        ("{term foo =\n    (s|[drop]\n", true),
+        ("{term foo =\n    (s|[drop])\n}", false),
+        ("[[]", true),
+        ("-- (\n", false),
+        ("( -- )\n", true),
+        ("--Comment (\n", false),
+        (")(", false),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(is_incomplete(input), expected, "Failed on {:?}", input);
+    }
+}
+
+#[test]
+fn test_interp_interrupt() {
+    let mut buffer = Vec::with_capacity(4096);
+    let mut interp = Interp::default();
+    interp
+        .interp_start("{term loop = loop} (sp|(s|[v] loop))", &mut buffer)
+        .unwrap();
+    for _ in 0..10 {
+        interp.interp_step(&mut buffer).unwrap();
+    }
+    assert!(!interp.is_done());
+    buffer.clear();
+    interp.interrupt(&mut buffer).unwrap();
+    let output = unsafe { std::str::from_utf8_unchecked(&buffer[..]) };
+    assert_eq!(output, "⇓ ⟨s|[v]⟩    (sp|(s|loop))\nInterrupted.\n");
+    assert!(interp.is_done());
+    buffer.clear();
+    interp.interp_start(":show loop", &mut buffer).unwrap();
+    let output = unsafe { std::str::from_utf8_unchecked(&buffer[..]) };
+    assert_eq!(output, "{term loop = loop}\n");
+    // Interrupting when nothing is running does nothing.
+    buffer.clear();
+    interp.interrupt(&mut buffer).unwrap();
+    assert!(buffer.is_empty());
+    assert!(interp.is_done());
+}
+
+#[test]
+fn test_interp_extra_help() {
+    let mut interp = Interp::default();
+    let extra_help = "   :history                 list the input history\n";
+    interp.set_extra_help(extra_help);
+    assert_eq!(run(&mut interp, ":help"), format!("{}{}", HELP, extra_help));
+    run(&mut interp, ":reset");
+    assert_eq!(run(&mut interp, ":help"), format!("{}{}", HELP, extra_help));
+}
+
+#[test]
+fn test_interp_state() {
+    let mut buffer = Vec::with_capacity(4096);
+    let mut interp = Interp::default();
+    interp
+        .interp_start("(sp|(s|[v] drop drop))", &mut buffer)
+        .unwrap();
+    assert_eq!(interp.last_step(), None);
+    assert_eq!(
+        interp.expr().map(|e| e.to_string()),
+        Some("(sp|(s|[v] drop drop))".to_owned())
+    );
+    let mut results = vec![];
+    while !interp.is_done() {
+        interp.interp_step(&mut buffer).unwrap();
+        results.push(interp.last_step().map(|r| r.map_err(|err| err.to_string())));
+        if results.len() == 4 {
+            assert_eq!(interp.value_multistack().to_string(), "⟨s|[v]⟩");
+        }
+    }
+    assert_eq!(
+        results,
+        vec![
+            Some(Ok(SmallStepRule::StkCtxDistr)),
+            Some(Ok(SmallStepRule::StkCtxDistr)),
+            Some(Ok(SmallStepRule::LitQuote)),
+            Some(Ok(SmallStepRule::StkCtxDistr)),
+            Some(Ok(SmallStepRule::StkCtxDistr)),
+            Some(Ok(SmallStepRule::IntrDrop)),
+            Some(Err("Expected 1 values. Found 0.".to_owned())),
+        ]
+    );
+    assert_eq!(interp.expr(), None);
+    assert_eq!(interp.value_multistack().to_string(), "");
+    assert!(!interp.parse_failed());
+    interp.interp_start("(s|[v]", &mut buffer).unwrap();
+    assert!(interp.parse_failed());
+    assert!(interp.is_done());
+}
+
+#[test]
+fn test_interp_debug() {
+    let mut interp = Interp::default();
+    let cases = [
+        (":step", "Not debugging.\n"),
+        ("{term dup = clone}", "Defined `dup`.\n"),
+        ("{term two = dup dup}", "Defined `two`.\n"),
+        (":break", "No breakpoints or watchpoints.\n"),
+        (":break dup", "Breakpoint set on `dup`.\n"),
+        (":watch t", "Watchpoint set on `t`.\n"),
+        (":watch s 5", "Watchpoint set on `s`.\n"),
+        (
+            ":break",
+            "Breakpoint on `dup`.\nWatchpoint on `t`.\nWatchpoint on `s` exceeding 5 values.\n",
+        ),
+        (":unwatch s", "Watchpoint removed from `s`.\n"),
+        (
+            ":debug (s|[v] two) (t|[w]) (s|drop)",
+            "    (__|(_|(s|[v] two) (t|[w]) (s|drop)))\n",
+        ),
+        (
+            ":step",
+            "‒StkCtxDistr⟶     (__|(_|(s|[v] two)) (_|(t|[w]) (s|drop)))\n",
+        ),
+        (
+            ":continue",
+            "Breakpoint `dup`.\n\
+             ⟨s|[v]⟩    (_|(s|dup)) (_|(s|dup)) (__|(_|(t|[w]) (s|drop)))\n",
+        ),
+        (
+            ":where",
+            "At step 9:\n  in two, called at step 7\n\
+             ⟨s|[v]⟩    (_|(s|dup)) (_|(s|dup)) (__|(_|(t|[w]) (s|drop)))\n",
+        ),
+        (
+            ":next",
+            "⟨s|[v] [v]⟩    (_|(s|dup)) (__|(_|(t|[w]) (s|drop)))\n",
+        ),
+        (":finish", "⟨s|[v] [v] [v]⟩    (__|(_|(t|[w]) (s|drop)))\n"),
+        (
+            ":continue",
+            "Stack `t` changed.\n⟨s|[v] [v] [v]⟩ ⟨t|[w]⟩    (__|(_|(s|drop)))\n",
+        ),
+        (":continue", "⇓ ⟨s|[v] [v]⟩ ⟨t|[w]⟩    \n"),
+        (":continue", "Evaluation finished.\n"),
+        (":where", "At step 19:\n⟨s|[v] [v]⟩ ⟨t|[w]⟩    \n"),
+        (":unbreak dup", "Breakpoint removed from `dup`.\n"),
+        (":unbreak dup", "No breakpoint on `dup`.\n"),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(run(&mut interp, input), expected, "Failed on {:?}", input);
+    }
+}
+
+#[test]
+fn test_interp_time_travel() {
+    let mut interp = Interp::default();
+    assert_eq!(run(&mut interp, ":back"), "Not debugging.\n");
+    run(&mut interp, "{term drain = (s|drop) drain}");
+    run(
+        &mut interp,
+        &format!(":debug (s|{}) drain", "[v] ".repeat(100)),
+    );
+    let at_600 = run(&mut interp, ":goto 600");
+    assert!(at_600.starts_with("At step 600:\n  in drain, called at step "));
+    let at_error = run(&mut interp, ":continue");
+    assert!(at_error.starts_with("Expected 1 values. Found 0.\n"));
+    let at_error = run(&mut interp, ":where");
+    let error_step: usize = at_error["At step ".len()..at_error.find(':').unwrap()]
+        .parse()
+        .unwrap();
+    assert_eq!(run(&mut interp, ":goto 600"), at_600);
+    assert_eq!(run(&mut interp, ":goto 3"), run(&mut interp, ":back 0"));
+    assert_eq!(run(&mut interp, &format!(":goto {}", error_step)), at_error);
+    assert_eq!(
+        run(&mut interp, ":back"),
+        run(&mut interp, &format!(":goto {}", error_step - 1))
+    );
+    assert_eq!(
+        run(&mut interp, ":goto 0"),
+        format!(
+            "At step 0:\n    (__|(_|(s|{}) drain))\n",
+            "[v] ".repeat(100).trim_end()
+        )
+    );
+    assert_eq!(
+        run(&mut interp, &format!(":back {}", error_step)),
+        "Cannot go back before step 0.\n"
+    );
+    run(&mut interp, ":goto 5");
+    run(&mut interp, "{term drain = (s|drop)}");
+    assert_eq!(run(&mut interp, ":back"), "Cannot go back before step 5.\n");
+    assert!(run(&mut interp, ":step").starts_with("‒"));
+    assert_eq!(run(&mut interp, ":back"), run(&mut interp, ":goto 5"));
+}
+
+#[test]
+fn test_interp_trace_options() {
+    let cases = [
+        (
+            ":trace -hide StkCtxDistr StkCtxEmpty (s|[v] dup2)",
+            "    (_|(s|[v] dup2))\n\
+             ‒LitQuote⟶ ⟨s|[v]⟩    (_|(s|dup2))\n\
+             ‒LitCall⟶ ⟨s|[v]⟩    (_|(s|dup dup))\n\
+             ‒LitCall⟶ ⟨s|[v]⟩    (_|(s|clone)) (_|(s|dup))\n\
+             ‒IntrClone⟶ ⟨s|[v] [v]⟩    (_|(s|dup))\n\
+             ‒LitCall⟶ ⟨s|[v] [v]⟩    (_|(s|clone))\n\
+             ‒IntrClone⟶ ⟨s|[v] [v] [v]⟩    \n",
+        ),
+        (
+            ":trace -show IntrClone (s|[v] dup2)",
+            "    (_|(s|[v] dup2))\n\
+             ‒IntrClone⟶ ⟨s|[v] [v]⟩    (_|(s|dup))\n\
+             ‒IntrClone⟶ ⟨s|[v] [v] [v]⟩    \n",
+        ),
+        (
+            ":trace -in dup -hide IntrClone (s|[v] dup2)",
+            "    (_|(s|[v] dup2))\n\
+             ‒LitCall⟶ ⟨s|[v]⟩    (_|(s|clone)) (_|(s|dup))\n\
+             ‒LitCall⟶ ⟨s|[v] [v]⟩    (_|(s|clone))\n",
+        ),
+        (
+            ":trace -steps 2..3 (s|[v] dup2)",
+            "    (_|(s|[v] dup2))\n\
+             ‒StkCtxDistr⟶     (_|(s|[v])) (_|(s|dup2))\n\
+             ‒LitQuote⟶ ⟨s|[v]⟩    (_|(s|dup2))\n",
+        ),
+        (
+            ":trace -every 4 (s|[v] dup2)",
+            "    (_|(s|[v] dup2))\n\
+             ‒LitCall⟶ ⟨s|[v]⟩    (_|(s|dup dup))\n\
+             ‒IntrClone⟶ ⟨s|[v] [v]⟩    (_|(s|dup))\n",
+        ),
+    ];
+    for (input, expected) in cases {
+        let mut interp = Interp::default();
+        run(&mut interp, "{term dup = clone} {term dup2 = dup dup}");
+        assert_eq!(run(&mut interp, input), expected, "Failed on {:?}", input);
+    }
+
+    let mut interp = Interp::default();
+    run(&mut interp, "{term dup = clone} {term dup2 = dup dup}");
+    let mut buffer = Vec::with_capacity(4096);
+    let options = TraceOptions {
+        show: vec![SmallStepRule::IntrClone],
+        in_term: Some("dup".to_owned()),
+        steps: Some(10..=20),
+        ..TraceOptions::default()
+    };
+    interp
+        .trace_start(options, "(s|[v] dup2)", &mut buffer)
+        .unwrap();
+    while !interp.is_done() {
+        interp.interp_step(&mut buffer).unwrap();
+    }
+    assert_eq!(
+        String::from_utf8(buffer).unwrap(),
+        "    (_|(s|[v] dup2))\n‒IntrClone⟶ ⟨s|[v] [v] [v]⟩    \n"
+    );
+}
+
+#[test]
+fn test_interp_trace_tree() {
+    let cases = [
+        (
+            ":trace -tree (s|[v] f)",
+            "    (_|(s|[v] f))\n\
+             ⟨s|[v]⟩    (_|(s|f)) ⇓ ⟨s|[v] [v] [v]⟩\n  \
+               ⟨s|[v]⟩    (_|(s|dup2)) ⇓ ⟨s|[v] [v] [v]⟩\n    \
+                 ⟨s|[v]⟩    (_|(s|dup)) ⇓ ⟨s|[v] [v]⟩\n    \
+                 ⟨s|[v] [v]⟩    (_|(s|dup)) ⇓ ⟨s|[v] [v] [v]⟩\n  \
+               ⟨s|[v] [v] [v] [dup]⟩    (_|(s|apply)) ⇓ ⟨s|[v] [v] [v] [v]⟩\n    \
+                 ⟨s|[v] [v] [v]⟩    (_|(s|dup)) ⇓ ⟨s|[v] [v] [v] [v]⟩\n\
+             ⇓ ⟨s|[v] [v] [v]⟩    \n",
+        ),
+        (
+            ":trace -tree -depth 1 (s|[v] f dup2)",
+            "    (_|(s|[v] f dup2))\n\
+             ⟨s|[v]⟩    (_|(s|f)) ⇓ ⟨s|[v] [v] [v]⟩\n\
+             ⟨s|[v] [v] [v]⟩    (_|(s|dup2)) ⇓ ⟨s|[v] [v] [v] [v] [v]⟩\n\
+             ⇓ ⟨s|[v] [v] [v] [v] [v]⟩    \n",
+        ),
+        (
+            ":trace -tree (s|[v] bad)",
+            "    (_|(s|[v] bad))\n\
+             ⟨s|[v]⟩    (_|(s|bad)) ⇓ …\n  \
+               ⟨s|[v]⟩    (_|(s|dup)) ⇓ ⟨s|[v] [v]⟩\n\
+             Expected 1 values. Found 0.\n",
+        ),
+    ];
+    for (input, expected) in cases {
+        let mut interp = Interp::default();
+        run(
+            &mut interp,
+            "{term dup = clone} {term dup2 = dup dup} {term f = dup2 [dup] apply drop}\n\
+             {term bad = dup drop drop drop}",
+        );
+        assert_eq!(run(&mut interp, input), expected, "Failed on {:?}", input);
+        let tree = interp.call_tree();
+        assert_eq!(
+            tree.iter()
+                .map(|call| format!("{}\n", call))
+                .collect::<String>(),
+            expected
+                .lines()
+                .skip(1)
+                .filter(|line| !line.starts_with(['⇓', 'E']))
+                .map(|line| format!("{}\n", line))
+                .collect::<String>()
+        );
+    }
+}
+
+#[test]
+fn test_interp_stats() {
+    let mut interp = Interp::default();
+    assert_eq!(
+        run(&mut interp, ":stats"),
+        "No statistics collected. Use `:stats on` or `:time <expr>`.\n"
+    );
+    run(&mut interp, "{term dup = clone} {term dup2 = dup dup}");
+    let output = run(&mut interp, ":time (s|[v] dup2) (t|[w])");
+    let report = "    (__|(_|(s|[v] dup2) (t|[w])))\n\
+                  ⇓ ⟨s|[v] [v] [v]⟩ ⟨t|[w]⟩    \n\
+                  Steps: 15\n  \
+                    IntrClone       2\n  \
+                    LitCall         3\n  \
+                    LitQuote        2\n  \
+                    StkCtxDistr     6\n  \
+                    StkCtx3Redund   2\n\
+                  Calls:\n  \
+                    dup             2\n  \
+                    dup2            1\n\
+                  Peak expression size: 13\n\
+                  Peak stack sizes (2 stacks touched):\n  \
+                    s               3\n  \
+                    t               1\n\
+                  Time: ";
+    assert!(output.starts_with(report), "{}", output);
+    let stats = interp.stats().unwrap();
+    assert_eq!(stats.steps, 15);
+    assert!(stats.elapsed.is_some());
+    assert_eq!(
+        run(&mut interp, ":stats"),
+        &output[output.find("Steps").unwrap()..]
+    );
+    // Evaluations are only measured when asked to.
+    run(&mut interp, "(s|drop)");
+    assert_eq!(interp.stats().unwrap().steps, 15);
+    assert_eq!(run(&mut interp, ":stats on"), "Collecting statistics.\n");
+    run(&mut interp, "(s|drop)");
+    let stats = interp.stats().unwrap();
+    assert_eq!(stats.steps, 1);
+    assert_eq!(stats.rules, vec![(SmallStepRule::IntrDrop, 1)]);
+    assert!(stats.calls.is_empty());
+    assert_eq!(
+        run(&mut interp, ":stats off"),
+        "Not collecting statistics.\n"
+    );
+    assert!(run(&mut interp, ":stats of").starts_with("User"));
+}
+
+#[test]
+fn test_interp_profile() {
+    let mut interp = Interp::default();
+    run(
+        &mut interp,
+        "{term dup = clone} {term dup2 = dup dup} {term f = dup2 [dup] apply drop}",
+    );
+    let collapsed = "eval 4\n\
+                     eval;f 10\n\
+                     eval;f;apply 1\n\
+                     eval;f;apply;dup 1\n\
+                     eval;f;dup2 4\n\
+                     eval;f;dup2;dup 2\n";
+    assert_eq!(
+        run(&mut interp, ":profile (s|[v] f)"),
+        format!("    (_|(s|[v] f))\n⇓ ⟨s|[v] [v] [v]⟩    \n{}", collapsed)
+    );
+    let mut written = vec![];
+    interp
+        .profile()
+        .unwrap()
+        .write_collapsed(&mut written)
+        .unwrap();
+    assert_eq!(String::from_utf8(written).unwrap(), collapsed);
+    // Evaluations are only profiled when asked to.
+    run(&mut interp, "(s|drop)");
+    assert_eq!(interp.profile().unwrap().to_string(), collapsed);
+    interp.set_profiling(true);
+    run(&mut interp, "(s|drop f)");
+    assert_eq!(
+        interp.profile().unwrap().stacks,
+        vec![
+            (vec![], 4),
+            (vec!["f".to_owned()], 10),
+            (vec!["f".to_owned(), "apply".to_owned()], 1),
+            (
+                vec!["f".to_owned(), "apply".to_owned(), "dup".to_owned()],
+                1
+            ),
+            (vec!["f".to_owned(), "dup2".to_owned()], 4),
+            (vec!["f".to_owned(), "dup2".to_owned(), "dup".to_owned()], 2),
+        ]
+    );
+}
+
+#[test]
+fn test_interp_tests() {
+    let mut interp = Interp::default();
+    assert_eq!(run(&mut interp, ":test"), "0 passed, 0 failed.\n");
+    assert_eq!(
+        run(
+            &mut interp,
+            "{term dup = clone}\n\
+             {test swap: ⟨s|a b⟩ (s|swap) ⇓ ⟨s|b a⟩}\n\
+             {test dup: ⟨s|a⟩ (s|dup) ⇓ ⟨s|a⟩ ⟨t|b⟩}"
+        ),
+        "Defined `dup`.\nDefined test `swap`.\nDefined test `dup`.\n"
+    );
+    assert_eq!(
+        run(&mut interp, ":test"),
+        "test swap ... ok (15 steps)\n\
+         test dup ... FAILED\n  \
+           - ⟨s|a⟩\n  \
+           + ⟨s|a a⟩\n  \
+           - ⟨t|b⟩\n\
+         1 passed, 1 failed.\n"
+    );
+    assert_eq!(
+        run(
+            &mut interp,
+            "{test dup: ⟨s|a⟩ (s|dup) ⇓ ⟨s|a a⟩}\n\
+             {test underflow: ⟨s|a⟩ (s|drop drop) ⇓}\n\
+             {test loop 10: (s|[clone apply] clone apply) ⇓}"
+        ),
+        "Redefined test `dup`.\nDefined test `underflow`.\nDefined test `loop`.\n"
+    );
+    assert_eq!(
+        run(&mut interp, ":test"),
+        "test swap ... ok (15 steps)\n\
+         test dup ... ok (2 steps)\n\
+         test underflow ... FAILED\n  \
+           Expected 1 values. Found 0. (after 3 steps)\n      (_|(s|drop))\n\
+         test loop ... FAILED\n  \
+           Reached the limit of 10 steps.\n  \
+           ⟨s|[clone apply] [clone apply]⟩    (_|(s|apply))\n\
+         2 passed, 2 failed.\n"
+    );
+    // Tests do not change the value multistack.
+    assert_eq!(interp.value_multistack().to_string(), "");
+    run(&mut interp, ":clear");
+    assert_eq!(run(&mut interp, ":test"), "0 passed, 0 failed.\n");
+}
+
+#[test]
+fn test_interp_assert() {
+    let mut interp = Interp::default();
+    run(&mut interp, "{term dup = clone}");
+    let cases = [
+        (
+            ":assert ⟨s|v⟩ (t|(s|clone)) ‒IntrClone⟶ ⟨s|v v⟩",
+            "Assertion holds.\n",
+        ),
+        (
+            ":assert ⟨s|v⟩ (t|(s|dup)) ‒LitCall⟶ ⟨s|v⟩ (t|(s|clone))",
+            "Assertion holds.\n",
+        ),
+        (
+            ":assert ⟨s|v⟩ (t|(s|clone)) ‒IntrDrop⟶ ⟨s|⟩",
+            "Assertion failed.\n  \
+               - ‒IntrDrop⟶\n  \
+               + ‒IntrClone⟶\n  \
+               + ⟨s|v v⟩\n",
+        ),
+        (
+            ":assert ⟨s|v⟩ (t|(s|clone drop)) ‒StkCtxDistr⟶ ⟨s|v⟩ (t|(s|clone)) (t|(s|drop))",
+            "Assertion failed.\n  \
+               - (t|(s|clone)) (t|(s|drop))\n  \
+               + (t|(s|clone) (s|drop))\n",
+        ),
+        // Expressions are compared up to the numbering of bound stacks.
+        (
+            ":assert ⟨s|[(s|e)]⟩ (t|(s|apply)) ‒IntrApply⟶ (t|(s|(s'3|e)))",
+            "Assertion holds.\n",
+        ),
+        (
+            ":assert ⟨s|[(s|e)]⟩ (t|(s|apply)) ‒IntrApply⟶ (t|(s|(t|e)))",
+            "Assertion failed.\n  \
+               - (t|(s|(t|e)))\n  \
+               + (t|(s|(s'1|e)))\n",
+        ),
+        (
+            ":assert ⟨s|⟩ (t|(s|drop)) ‒IntrDrop⟶",
+            "Assertion failed.\n  Expected 1 values. Found 0.\n",
+        ),
+        (":assert ⟨s|a b⟩ (s|swap) ⇓ ⟨s|b a⟩", "Assertion holds.\n"),
+        (
+            ":assert ⟨s|a⟩ (s|dup) ⇓ ⟨s|a⟩",
+            "Assertion failed.\n  - ⟨s|a⟩\n  + ⟨s|a a⟩\n",
+        ),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(run(&mut interp, input), expected, "Failed on {:?}", input);
+    }
+}
+
+#[test]
+fn test_interp_edit_stacks() {
+    let mut interp = Interp::default();
+    let cases = [
+        (":set ⟨s|a b⟩ ⟨t|c⟩", "⟨s|a b⟩ ⟨t|c⟩\n"),
+        (":set ⟨t|d⟩ ⟨u|[e f]⟩", "⟨s|a b⟩ ⟨t|d⟩ ⟨u|[e f]⟩\n"),
+        (":set ⟨u|⟩", "⟨s|a b⟩ ⟨t|d⟩\n"),
+        (":push t [g] h", "⟨s|a b⟩ ⟨t|d [g] h⟩\n"),
+        (
+            ":push v [(s|(s|drop))]",
+            "⟨s|a b⟩ ⟨t|d [g] h⟩ ⟨v|[(s|(s'1|drop))]⟩\n",
+        ),
+        (":pop v", "⟨s|a b⟩ ⟨t|d [g] h⟩\n"),
+        (":pop t", "⟨s|a b⟩ ⟨t|d [g]⟩\n"),
+        (":pop v", "Stack `v` is empty.\n"),
+        (":drop t", "Stack `t` dropped.\n"),
+        ("(s|swap)", "⟨s|a b⟩    (_|(s|swap))\n⇓ ⟨s|b a⟩    \n"),
+        (":drop", "Values dropped.\n"),
+        (":pop s", "Stack `s` is empty.\n"),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(run(&mut interp, input), expected, "Failed on {:?}", input);
+    }
+}
+
+#[test]
+fn test_interp_ascii() {
+    let mut interp = Interp::default();
+    assert_eq!(run(&mut interp, ":ascii on"), "Using ASCII notation.\n");
+    assert_eq!(run(&mut interp, ":set <s|a>"), "<s|a>\n");
+    run(&mut interp, "{term dup = clone} {term g = (t|(t|[c]))}");
+    let trace = run(&mut interp, ":trace (s|[b] dup) g");
+    assert!(trace.ends_with("-LitQuote-> <s|a [b] [b]> <t'2|[c]>    \n"));
+    // Each step of the trace parses back as a judgement that holds.
+    let state = |line: &str| match line.strip_prefix('-') {
+        Some(rest) => rest[rest.find("-> ").unwrap() + 3..].to_owned(),
+        None => line.to_owned(),
+    };
+    let lines: Vec<&str> = trace.lines().collect();
+    for (before, step) in lines.iter().zip(lines.iter().skip(1)) {
+        let input = format!(":assert {} {}", state(before), step);
+        assert_eq!(run(&mut interp, &input), "Assertion holds.\n", "{}", input);
+    }
+    let input = format!(
+        ":assert {} ==> {}",
+        state(lines[0]),
+        state(lines[lines.len() - 1])
+    );
+    assert_eq!(run(&mut interp, &input), "Assertion holds.\n", "{}", input);
+    assert_eq!(
+        run(&mut interp, ":assert <s|a> (t|(s|drop)) -IntrDrop-> <s|b>"),
+        "Assertion failed.\n  - <s|b>\n"
+    );
+    assert_eq!(run(&mut interp, ":ascii off"), "Using Unicode notation.\n");
+    assert_eq!(
+        run(&mut interp, ":push s'1 v"),
+        "⟨s|a [b] [b]⟩ ⟨s'1|v⟩ ⟨t'2|[c]⟩\n"
+    );
+}
+
+#[test]
+fn test_interp_optimize() {
+    let mut interp = Interp::default();
+    assert_eq!(run(&mut interp, ":optimize foo"), "Not defined.\n");
+    assert_eq!(
+        run(&mut interp, ":optimize swap"),
+        "`swap` is already optimized.\n"
+    );
+    run(
+        &mut interp,
+        "{term dup = clone}\n\
+         {term f = (a|push)(a|pop) dup (t|[x])(t|pop)}\n\
+         {test f: ⟨s|a⟩ (s|f) ⇓ ⟨s|a a [x]⟩}",
+    );
+    run(&mut interp, ":push _ [b]");
+    assert_eq!(
+        run(&mut interp, ":optimize f"),
+        "Before: {term f = (a|push) (a|pop) dup (t|[x]) (t|pop)}\n\
+         After:  {term f = clone (t|[x] pop)}\n\
+         call on the current stacks: 19 steps before, 9 steps after\n\
+         test f: 19 steps before, 9 steps after\n\
+         Optimized `f`.\n"
+    );
+    assert_eq!(
+        run(&mut interp, ":optimize f"),
+        "`f` is already optimized.\n"
+    );
+    assert_eq!(
+        run(&mut interp, ":test"),
+        "test f ... ok (9 steps)\n1 passed, 0 failed.\n"
+    );
+    // Redefining a term that was inlined restores the original definition.
+    assert_eq!(
+        run(&mut interp, "{term dup = clone clone drop}"),
+        "Redefined `dup`.\n\
+         Restored the original definition of `f`, since `dup` was redefined.\n"
+    );
+    assert_eq!(
+        run(&mut interp, ":show f"),
+        "{term f = (a|push) (a|pop) dup (t|[x]) (t|pop)}\n"
+    );
+    // A call that fails before it is optimized is still measured.
+    run(&mut interp, ":drop");
+    assert_eq!(
+        run(&mut interp, ":optimize f"),
+        "Before: {term f = (a|push) (a|pop) dup (t|[x]) (t|pop)}\n\
+         After:  {term f = clone (t|[x] pop)}\n\
+         call on the current stacks: FAILED before, FAILED after\n\
+         test f: 25 steps before, 9 steps after\n\
+         Optimized `f`.\n"
+    );
+}
+
+#[test]
+fn test_interp_equiv() {
+    let mut interp = Interp::default();
+    // Errors are compared too, so `swap swap` differs from doing nothing.
+    assert_eq!(
+        run(&mut interp, ":equiv (s|swap swap) = (s|)"),
+        "Counterexample: no values\n  \
+           left:  Expected 1 values. Found 0.\n  \
+           right: no values\n"
+    );
+    assert_eq!(
+        run(&mut interp, ":equiv (s|[a] [b] swap swap) = (s|[a] [b])"),
+        "No difference found within bounds (225 inputs, with up to 3 values on each stack).\n"
+    );
+    // Placeholders are also quoted, so composing them finds a difference
+    // that failing on `?1` hides.
+    assert_eq!(
+        run(
+            &mut interp,
+            ":equiv (s|clone [x] compose) = (s|clone [x] swap compose)"
+        ),
+        "Counterexample: ⟨s|[?1]⟩\n  \
+           left:  ⟨s|[?1] [?1 x]⟩\n  \
+           right: ⟨s|[?1] [x ?1]⟩\n"
+    );
+    // Inputs on which both sides fail compare nothing.
+    assert_eq!(
+        run(&mut interp, ":equiv (s|[a] swap) = (s|[a] swap)"),
+        "No difference found within bounds (225 inputs, with up to 3 values on each stack).\n\
+         Inconclusive on 15 of them, where both sides failed or did not finish.\n"
+    );
+    assert_eq!(
+        run(&mut interp, ":equiv apply apply = apply"),
+        "No difference found within bounds (225 inputs, with up to 3 values on each stack).\n\
+         Inconclusive on 225 of them, where both sides failed or did not finish.\n"
+    );
+    assert_eq!(
+        run(&mut interp, ":equiv (s|push) = (t|push)"),
+        "Counterexample: ⟨_|?1⟩\n  left:  ⟨s|?1⟩\n  right: ⟨t|?1⟩\n"
+    );
+    assert_eq!(
+        run(&mut interp, ":equiv apply = drop"),
+        "Counterexample: ⟨_|?1⟩\n  left:  Undefined term: `?1`.\n  right: no values\n"
+    );
+    assert_eq!(
+        run(&mut interp, ":equiv [clone apply] clone apply ="),
+        "Counterexample: no values\n  \
+           left:  did not finish within 100000 steps\n  \
+           right: no values\n"
+    );
+    // The number of inputs grows exponentially with the number of stacks,
+    // so it is capped.
+    assert_eq!(
+        run(&mut interp, ":equiv (a|) (b|) (c|) (d|) (e|) (f|) (g|) ="),
+        "No difference found within bounds (4096 inputs, with up to 3 values on each stack).\n"
+    );
+    // Each step tries one input, so a comparison can be interrupted.
+    let mut output = vec![];
+    interp
+        .interp_start(":equiv (s|[a] [b] swap swap) = (s|[a] [b])", &mut output)
+        .unwrap();
+    interp.interp_step(&mut output).unwrap();
+    assert!(!interp.is_done());
+    interp.interrupt(&mut output).unwrap();
+    assert!(interp.is_done());
+    assert_eq!(String::from_utf8(output).unwrap(), "Interrupted.\n");
+}
+
+#[test]
+fn test_interp_prove() {
+    let mut interp = Interp::default();
+    assert_eq!(
+        run(&mut interp, ":prove [a] [b] swap = [b] [a]"),
+        "  (__|(_|[a] [b] swap))\n\
+         = (__|(_|[a] [b] (s1|push) (s2|push) (s1|pop) (s2|pop)))    by LitCall\n\
+         = (__|(_|[a] (s1|[b]) (s2|push) (s1|pop) (s2|pop)))    by IntrPush\n\
+         = (__|(_|(s1|[b]) [a] (s2|push) (s1|pop) (s2|pop)))    by Commute\n\
+         = (__|(_|(s1|[b]) (s2|[a]) (s1|pop) (s2|pop)))    by IntrPush\n\
+         = (__|(_|(s2|[a]) (s1|[b]) (s1|pop) (s2|pop)))    by Commute\n\
+         = (__|(_|(s2|[a]) [b] (s2|pop)))    by IntrPop\n\
+         = (__|(_|[b] (s2|[a]) (s2|pop)))    by Commute\n\
+         = (__|(_|[b] [a]))    by IntrPop\n\
+         Proved.\n"
+    );
+    assert_eq!(
+        run(&mut interp, ":prove swap swap ="),
+        "No proof found within bounds (7 expressions searched).\n"
+    );
+    // Applied quotes are deshadowed in their stack contexts, as in
+    // evaluation.
+    assert_eq!(
+        run(&mut interp, ":prove (s|[(s|[a])] apply) = (s|(s|[a]))"),
+        "  (_|(s|[(s|[a])] apply))\n\
+         = (_|(s|(s'1|[a])))    by IntrApply\n\
+         = (s|(s'1|[a]))    by StkCtx3Redund\n\
+         Proved.\n"
+    );
+    assert_eq!(
+        run(&mut interp, "{law swap_swap: swap swap = }"),
+        "Law `swap_swap` does not hold.\n\
+         Counterexample: no values\n  \
+           left:  Expected 1 values. Found 0.\n  \
+           right: no values\n"
+    );
+    assert_eq!(
+        run(
+            &mut interp,
+            "{law not_not: True not not = True}\n\
+             {law park: [x] (a|push) (a|pop) = [x]}"
+        ),
+        "Defined law `not_not`.\n\
+         Defined law `park`.\n"
+    );
+    assert_eq!(
+        run(&mut interp, "{law apply_twice: apply apply = apply}"),
+        "Both sides of law `apply_twice` failed or did not finish on every input, so it is unchecked.\n\
+         Defined law `apply_twice`.\n"
+    );
+    // The definitions that follow a law are made once it is checked.
+    assert_eq!(
+        run(
+            &mut interp,
+            "{law swap_swap: swap swap = swap swap} {term id = } (s|[x] id)"
+        ),
+        "Defined law `swap_swap`.\n\
+         Defined `id`.\n    \
+         (_|(s|[x] id))\n\
+         ⇓ ⟨s|[x]⟩    \n"
+    );
+    assert_eq!(
+        run(
+            &mut interp,
+            ":prove (s|[x] (t|push) (t|pop) True not not) = (s|[x] True)"
+        ),
+        "  (_|(s|[x] (t|push) (t|pop) True not not))\n\
+         = (_|(s|[x] True not not))    by law `park`\n\
+         = (_|(s|[x] True))    by law `not_not`\n\
+         Proved.\n"
+    );
+    // A search can be interrupted between steps.
+    let mut output = vec![];
+    interp
+        .interp_start(":prove (s|Z S S Z S add) = (s|Z S S S)", &mut output)
+        .unwrap();
+    interp.interp_step(&mut output).unwrap();
+    assert!(!interp.is_done());
+    interp.interrupt(&mut output).unwrap();
+    assert!(interp.is_done());
+    assert_eq!(String::from_utf8(output).unwrap(), "Interrupted.\n");
+    run(&mut interp, ":clear");
+    assert_eq!(
+        run(&mut interp, ":prove True not not = True"),
+        "No proof found within bounds (2 expressions searched).\n"
+    );
+}
+
+#[test]
+fn test_interp_memo() {
+    let mut interp = Interp::default();
+    assert_eq!(
+        run(&mut interp, ":memo"),
+        "Not memoizing calls. Use `:memo on`.\n"
+    );
+    run(
+        &mut interp,
+        "{term fib =
+            (case_Z|[Z])
+            (case_S|[clone (case_Z|[drop Z S]) (case_S|[fib2]) apply])
+            apply
+        }
+        {term fib2 = fib swap fib add}",
+    );
+    // Memoized calls give the same results.
+    let srcs = [
+        "(s|Z fib)",
+        "(s|Z S S S S S fib)",
+        "(s|Z S S fib Z S S S fib fib)",
+        "(s|Z S S Z S S S mul Z S S Z S S S mul add)",
+        "(s|False not True and True True or not)",
+        "(a|[x] [y] swap quote2 [z] quote1 compose (b|push)) (b|pop apply)",
+        "(s|[(s|swap)] apply (t|[swap]) (t|pop) apply)",
+        "(s|[clone] clone apply)",
+        "(s|add)",
+    ];
+    for src in srcs.iter() {
+        run(&mut interp, ":memo off");
+        run(&mut interp, ":drop");
+        let expected = run(&mut interp, src);
+        run(&mut interp, ":memo on");
+        for _ in 0..2 {
+            run(&mut interp, ":drop");
+            assert_eq!(run(&mut interp, src), expected, "evaluating {}", src);
+        }
+    }
+    assert_eq!(run(&mut interp, ":memo off"), "Not memoizing calls.\n");
+    run(&mut interp, ":drop");
+    let count = |output: &str, before: &str, after: &str| -> usize {
+        let start = output.find(before).unwrap() + before.len();
+        let end = start + output[start..].find(after).unwrap();
+        output[start..end].parse().unwrap()
+    };
+    let fib = "(s|Z S S S S S S S S fib)";
+    let output = run(&mut interp, &format!(":time {}", fib));
+    let plain_steps = count(&output, "Steps: ", "\n");
+    assert_eq!(run(&mut interp, ":memo on"), "Memoizing calls.\n");
+    run(&mut interp, ":drop");
+    let output = run(&mut interp, &format!(":time {}", fib));
+    let memo_steps = count(&output, "Steps: ", "\n");
+    assert!(
+        memo_steps * 4 < plain_steps,
+        "{} {}",
+        memo_steps,
+        plain_steps
+    );
+    // The steps that memoized calls saved are counted.
+    let steps_saved = count(&output, " calls, ", " steps saved");
+    assert_eq!(memo_steps + steps_saved, plain_steps);
+    let report = |entries: usize| {
+        format!(
+            "Calls: 35 hits, 173 misses\n  \
+            succ            12 hits, 21 misses\n  \
+            S               9 hits, 21 misses\n  \
+            add             6 hits, 34 misses\n  \
+            fib             6 hits, 9 misses\n  \
+            Z               2 hits, 1 misses\n  \
+            _S              0 hits, 48 misses\n  \
+            _Z              0 hits, 3 misses\n  \
+            fib2            0 hits, 7 misses\n  \
+            quote0          0 hits, 1 misses\n  \
+            quote1          0 hits, 21 misses\n  \
+            swap            0 hits, 7 misses\n\
+            Memoized calls: {} (at most 100000, 0 evicted)\n\
+            Steps saved: 6085\n",
+            entries
+        )
+    };
+    assert_eq!(run(&mut interp, ":memo"), report(173));
+    // Redefining a term forgets the memoized calls.
+    run(&mut interp, "{term fib2 = fib swap fib swap add}");
+    assert_eq!(run(&mut interp, ":memo"), report(0));
+}
+
+#[test]
+fn test_interp_jets() {
+    let mut interp = Interp::default();
+    assert_eq!(
+        run(&mut interp, ":jets"),
+        "Not using jets. Use `:jets on` or `:jets check`.\n"
+    );
+    // Jets give the same results as the definitions, including on values
+    // that are not in the expected encoding, and along with memoized calls.
+    let srcs = [
+        "(s|Z S S Z S S S mul Z S S Z S S S mul add)",
+        "(s|False not True and True True or not)",
+        "(s|True False and False not or)",
+        "(s|[x] Z add)",
+        "(s|Z S [x] add)",
+        "(s|True [x] and)",
+        "(s|add)",
+        "(a|Z S S (b|Z S S S) (b|pop) mul) (a|True (c|False) (c|pop) or)",
+    ];
+    let modes = [
+        [":jets on", ":memo off"],
+        [":jets check", ":memo off"],
+        [":jets on", ":memo on"],
+    ];
+    for src in srcs.iter() {
+        run(&mut interp, ":jets off");
+        run(&mut interp, ":memo off");
+        run(&mut interp, ":drop");
+        let expected = run(&mut interp, src);
+        for mode in modes.iter() {
+            for command in mode.iter() {
+                run(&mut interp, command);
+            }
+            for _ in 0..2 {
+                run(&mut interp, ":drop");
+                assert_eq!(
+                    run(&mut interp, src),
+                    expected,
+                    "evaluating {} with {:?}",
+                    src,
+                    mode
+                );
+            }
+        }
+    }
+    run(&mut interp, ":memo off");
+    assert_eq!(run(&mut interp, ":jets off"), "Not using jets.\n");
+    run(&mut interp, ":drop");
+    let steps = |output: String| -> usize {
+        let start = output.find("Steps: ").unwrap() + "Steps: ".len();
+        let end = start + output[start..].find('\n').unwrap();
+        output[start..end].parse().unwrap()
+    };
+    let mul = ":time (s|Z S S S S S Z S S S S S mul)";
+    let plain_steps = steps(run(&mut interp, mul));
+    assert_eq!(run(&mut interp, ":jets on"), "Using jets.\n");
+    run(&mut interp, ":drop");
+    let jet_steps = steps(run(&mut interp, mul));
+    assert!(
+        jet_steps * 10 < plain_steps,
+        "{} {}",
+        jet_steps,
+        plain_steps
+    );
+    // Calls computed by jets are counted in statistics and profiles.
+    let untimed = |output: String| -> String {
+        output
+            .lines()
+            .filter(|line| !line.starts_with("Time: "))
+            .map(|line| format!("{}\n", line))
+            .collect()
+    };
+    run(&mut interp, ":drop");
+    assert_eq!(
+        untimed(run(&mut interp, ":time (s|Z S Z S S mul)")),
+        "    (_|(s|Z S Z S S mul))\n\
+        ⇓ ⟨s|[[[_Z] _S] _S]⟩    \n\
+        Steps: 55\n  \
+        IntrQuote       3\n  \
+        IntrCompose     5\n  \
+        LitCall         10\n  \
+        LitQuote        7\n  \
+        StkCtxDistr     30\n\
+        Calls:\n  \
+        S               3\n  \
+        quote1          3\n  \
+        Z               2\n  \
+        quote0          2\n  \
+        mul             1\n\
+        Skipped calls:\n  \
+        jets            1 calls\n\
+        Peak expression size: 19\n\
+        Peak stack sizes (1 stacks touched):\n  \
+        s               3\n"
+    );
+    run(&mut interp, ":drop");
+    assert_eq!(
+        run(&mut interp, ":profile (s|Z S Z S S mul True not)"),
+        "    (_|(s|Z S Z S S mul True not))\n\
+        ⇓ ⟨s|[[[_Z] _S] _S] [_False]⟩    \n\
+        eval 20\n\
+        eval;S 21\n\
+        eval;S;quote1 3\n\
+        eval;True 7\n\
+        eval;True;quote0 1\n\
+        eval;Z 14\n\
+        eval;Z;quote0 2\n\
+        eval;mul (jet) 1\n\
+        eval;not (jet) 1\n"
+    );
+    // Jets are not used while every step is traced.
+    run(&mut interp, ":drop");
+    assert!(
+        run(&mut interp, ":trace (s|True not)").starts_with("Jets are not used while tracing.\n")
+    );
+    assert_eq!(
+        run(&mut interp, ":jets check"),
+        "Using jets, checked against their definitions.\n"
+    );
+    // Checked jets know how many steps they saved.
+    run(&mut interp, ":drop");
+    assert_eq!(
+        untimed(run(&mut interp, ":time (s|Z S Z S S mul)")),
+        "    (_|(s|Z S Z S S mul))\n\
+        ⇓ ⟨s|[[[_Z] _S] _S]⟩    \n\
+        Steps: 55\n  \
+        IntrQuote       3\n  \
+        IntrCompose     5\n  \
+        LitCall         10\n  \
+        LitQuote        7\n  \
+        StkCtxDistr     30\n\
+        Calls:\n  \
+        S               3\n  \
+        quote1          3\n  \
+        Z               2\n  \
+        quote0          2\n  \
+        mul             1\n\
+        Skipped calls:\n  \
+        jets            1 calls, 265 steps saved\n\
+        Peak expression size: 19\n\
+        Peak stack sizes (1 stacks touched):\n  \
+        s               3\n"
+    );
+    run(&mut interp, ":drop");
+    run(&mut interp, "(s|Z S S Z S add True not)");
+    let report = |add: &str, mul: &str| {
+        format!(
+            "Using jets, checked against their definitions.\n  \
+            not             2 uses\n  \
+            or              0 uses\n  \
+            and             0 uses\n  \
+            add             {}\n  \
+            mul             {}\n",
+            add, mul
+        )
+    };
+    assert_eq!(run(&mut interp, ":jets"), report("1 uses", "4 uses"));
+    // Redefining a term that a jet depends on turns the jet off.
+    run(&mut interp, "{term succ = quote1 [_S] compose}");
+    let off = "off, since `succ` was redefined";
+    assert_eq!(run(&mut interp, ":jets"), report(off, off));
+    // Restoring its definition turns the jet back on.
+    run(&mut interp, "{term succ = S}");
+    assert_eq!(run(&mut interp, ":jets"), report("1 uses", "4 uses"));
+}
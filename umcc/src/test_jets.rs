@@ -0,0 +1,147 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::builtin::TERM_DEF_SRCS;
+use crate::core::*;
+use crate::jets::*;
+use crate::parse::*;
+
+fn builtin_context() -> Context {
+    let mut ctx = Context::default();
+    for term_def_src in TERM_DEF_SRCS.iter() {
+        let term_def = TermDefParser::new()
+            .parse(&mut ctx.interner, term_def_src)
+            .unwrap();
+        assert_eq!(ctx.define_term(term_def), None);
+    }
+    ctx
+}
+
+/// Evaluate `src` as far as it goes, to make the values a jet reads.
+fn eval(ctx: &mut Context, src: &str) -> ValueMultistack {
+    let mut vms = ValueMultistack::default();
+    let src = format!("(_|{})", src);
+    let mut e = ExprParser::new().parse(&mut ctx.interner, &src).unwrap();
+    e.deshadow();
+    while e != Expr::default() {
+        ctx.small_step(&mut vms, &mut e).unwrap();
+    }
+    vms
+}
+
+fn jet_step(ctx: &mut Context, jets: &mut Jets, args: &str, term: &str) -> JetOutcome {
+    let mut vms = eval(ctx, args);
+    let src = format!("(_|(s|{}))", term);
+    let mut e = ExprParser::new().parse(&mut ctx.interner, &src).unwrap();
+    jets.step(ctx, &mut vms, &mut e, None)
+}
+
+#[test]
+fn test_jets_agree_with_definitions() {
+    let mut ctx = builtin_context();
+    let mut jets = Jets::new(&mut ctx.interner, true);
+    let nat = |n: usize| format!("Z{}", " S".repeat(n));
+    for a in 0..6 {
+        for b in 0..6 {
+            for term in ["add", "mul"].iter() {
+                let args = format!("(s|{} {})", nat(a), nat(b));
+                assert!(
+                    matches!(
+                        jet_step(&mut ctx, &mut jets, &args, term),
+                        JetOutcome::Applied(_, Some(_))
+                    ),
+                    "{} {}",
+                    args,
+                    term
+                );
+            }
+        }
+    }
+    for a in ["False", "True"].iter() {
+        let args = format!("(s|{})", a);
+        assert!(
+            matches!(
+                jet_step(&mut ctx, &mut jets, &args, "not"),
+                JetOutcome::Applied(_, Some(_))
+            ),
+            "{} not",
+            args
+        );
+        for b in ["False", "True"].iter() {
+            for term in ["and", "or"].iter() {
+                let args = format!("(s|{} {})", a, b);
+                assert!(
+                    matches!(
+                        jet_step(&mut ctx, &mut jets, &args, term),
+                        JetOutcome::Applied(_, Some(_))
+                    ),
+                    "{} {}",
+                    args,
+                    term
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_jets_skip() {
+    let mut ctx = builtin_context();
+    let mut jets = Jets::new(&mut ctx.interner, false);
+    let cases = [
+        // Too few values.
+        ("(s|Z)", "add"),
+        // Values that are not in the expected encoding.
+        ("(s|[x] Z)", "add"),
+        ("(s|Z [Z S])", "mul"),
+        ("(s|Z)", "not"),
+        ("(s|True [_True _True])", "and"),
+        // Terms without jets.
+        ("(s|Z)", "succ"),
+    ];
+    for (args, term) in cases.iter() {
+        assert_eq!(
+            jet_step(&mut ctx, &mut jets, args, term),
+            JetOutcome::Skipped,
+            "{} {}",
+            args,
+            term
+        );
+    }
+    // In checking mode, calls that take too long to check are evaluated as
+    // usual.
+    let nat = format!("Z{}", " S".repeat(30));
+    let args = format!("(s|{} {})", nat, nat);
+    jets.set_check(true);
+    assert_eq!(
+        jet_step(&mut ctx, &mut jets, &args, "mul"),
+        JetOutcome::Skipped
+    );
+    assert_eq!(jets.report(&ctx).jets[4].unchecked, 1);
+    jets.set_check(false);
+    assert!(matches!(
+        jet_step(&mut ctx, &mut jets, &args, "mul"),
+        JetOutcome::Applied(_, None)
+    ));
+    // Redefining a term turns off the jets that depend on it.
+    let term_def = TermDefParser::new()
+        .parse(&mut ctx.interner, "{term _S = (case_S|pop) apply}")
+        .unwrap();
+    ctx.define_term(term_def);
+    jets.forget();
+    for term in ["add", "mul"].iter() {
+        assert_eq!(
+            jet_step(&mut ctx, &mut jets, "(s|Z Z)", term),
+            JetOutcome::Skipped,
+            "{}",
+            term
+        );
+    }
+    assert!(matches!(
+        jet_step(&mut ctx, &mut jets, "(s|True)", "not"),
+        JetOutcome::Applied(_, None)
+    ));
+}
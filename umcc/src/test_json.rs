@@ -0,0 +1,149 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::core::*;
+use crate::display::*;
+use crate::interp::Interp;
+use crate::json::*;
+use crate::parse::*;
+use crate::trace::JsonTraceSink;
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+#[test]
+fn test_json_expr() {
+    let cases = [
+        ("", r#"{"type":"compose","exprs":[]}"#),
+        ("push", r#"{"type":"intrinsic","name":"push"}"#),
+        ("foo", r#"{"type":"call","name":"foo"}"#),
+        (
+            "[foo] (s|)",
+            r#"{"type":"compose","exprs":[{"type":"quote","expr":{"type":"call","name":"foo"}},{"type":"stack_context","stack":"s","expr":{"type":"compose","exprs":[]}}]}"#,
+        ),
+    ];
+    for (e_src, expected) in cases {
+        let interner = &mut Interner::default();
+        let e = ExprParser::new().parse(interner, e_src).unwrap();
+        assert_eq!(Json(&e.resolve(interner)).to_string(), expected);
+    }
+}
+
+#[test]
+fn test_json_value_multistack() {
+    let interner = &mut Interner::default();
+    let vms = ValueMultistackParser::new()
+        .parse(interner, "⟨t|[]⟩ ⟨s|v [v]⟩")
+        .unwrap();
+    assert_eq!(
+        Json(&vms.resolve(interner)).to_string(),
+        r#"[{"stack":"s","values":[{"type":"call","name":"v"},{"type":"quote","expr":{"type":"call","name":"v"}}]},{"stack":"t","values":[{"type":"quote","expr":{"type":"compose","exprs":[]}}]}]"#
+    );
+}
+
+#[test]
+fn test_json_eval_error() {
+    let interner = &mut Interner::default();
+    let sym = TermSymbol(interner.get_or_intern_static("a\"b"));
+    let cases = [
+        (
+            EvalError::TooFewValues {
+                available: 0,
+                expected: 1,
+            },
+            r#"{"type":"TooFewValues","available":0,"expected":1,"message":"Expected 1 values. Found 0."}"#,
+        ),
+        (
+            EvalError::UndefinedTerm(sym),
+            r#"{"type":"UndefinedTerm","name":"a\"b","message":"Undefined term: `a\"b`."}"#,
+        ),
+    ];
+    for (err, expected) in cases {
+        assert_eq!(Json(&err.resolve(interner)).to_string(), expected);
+    }
+}
+
+#[test]
+fn test_json_call_tree() {
+    let mut buffer = Vec::with_capacity(4096);
+    let mut interp = Interp::default();
+    interp
+        .interp_start("{term dup = clone}", &mut buffer)
+        .unwrap();
+    interp
+        .interp_start(":trace -tree (s|[v] dup)", &mut buffer)
+        .unwrap();
+    while !interp.is_done() {
+        interp.interp_step(&mut buffer).unwrap();
+    }
+    assert_eq!(
+        Json(&interp.call_tree()).to_string(),
+        r#"[{"expr":{"type":"stack_context","stack":"_","expr":{"type":"stack_context","stack":"s","expr":{"type":"call","name":"dup"}}},"before":[{"stack":"s","values":[{"type":"quote","expr":{"type":"call","name":"v"}}]}],"after":[{"stack":"s","values":[{"type":"quote","expr":{"type":"call","name":"v"}},{"type":"quote","expr":{"type":"call","name":"v"}}]}],"calls":[]}]"#
+    );
+}
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_json_trace_sink() {
+    let run = |full: bool| -> Vec<String> {
+        let lines = SharedBuffer::default();
+        let mut buffer = Vec::with_capacity(4096);
+        let mut interp = Interp::default();
+        interp.set_json_trace(Some(JsonTraceSink::new(Box::new(lines.clone()), full)));
+        interp
+            .interp_start("(sp|(s|[v] clone))", &mut buffer)
+            .unwrap();
+        while !interp.is_done() {
+            interp.interp_step(&mut buffer).unwrap();
+        }
+        let lines = String::from_utf8(lines.0.borrow().clone()).unwrap();
+        lines.lines().map(|line| line.to_owned()).collect()
+    };
+    let v = r#"{"type":"quote","expr":{"type":"call","name":"v"}}"#;
+    let full = run(true);
+    assert_eq!(full.len(), 4);
+    assert_eq!(
+        full[3],
+        format!(
+            r#"{{"step":4,"rule":"IntrClone","redex":[],"multistack":[{{"stack":"s","values":[{},{}]}}],"expr":{{"type":"compose","exprs":[]}}}}"#,
+            v, v
+        )
+    );
+    let diff = run(false);
+    assert_eq!(
+        diff,
+        vec![
+            format!(
+                r#"{{"step":1,"rule":"StkCtxDistr","redex":[],"stacks":[],"replaced_by":[{{"type":"stack_context","stack":"sp","expr":{{"type":"compose","exprs":[{{"type":"stack_context","stack":"s","expr":{}}},{{"type":"stack_context","stack":"s","expr":{{"type":"intrinsic","name":"clone"}}}}]}}}}]}}"#,
+                v
+            ),
+            format!(
+                r#"{{"step":2,"rule":"StkCtxDistr","redex":[],"stacks":[],"replaced_by":[{{"type":"stack_context","stack":"sp","expr":{{"type":"stack_context","stack":"s","expr":{}}}}},{{"type":"stack_context","stack":"sp","expr":{{"type":"stack_context","stack":"s","expr":{{"type":"intrinsic","name":"clone"}}}}}}]}}"#,
+                v
+            ),
+            format!(
+                r#"{{"step":3,"rule":"LitQuote","redex":[0],"stacks":[{{"stack":"s","pop":0,"push":[{}]}}],"replaced_by":[]}}"#,
+                v
+            ),
+            format!(
+                r#"{{"step":4,"rule":"IntrClone","redex":[],"stacks":[{{"stack":"s","pop":0,"push":[{}]}}],"replaced_by":[]}}"#,
+                v
+            ),
+        ]
+    );
+}
@@ -0,0 +1,72 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::builtin::TERM_DEF_SRCS;
+use crate::core::*;
+use crate::display::*;
+use crate::memo::*;
+use crate::parse::*;
+
+fn builtin_context() -> Context {
+    let mut ctx = Context::default();
+    for term_def_src in TERM_DEF_SRCS.iter() {
+        let term_def = TermDefParser::new()
+            .parse(&mut ctx.interner, term_def_src)
+            .unwrap();
+        assert_eq!(ctx.define_term(term_def), None);
+    }
+    ctx
+}
+
+/// Evaluate `src`, using `memo` if there is one.
+fn eval(ctx: &mut Context, memo: Option<&mut Memo>, src: &str) -> ValueMultistack {
+    let mut vms = ValueMultistack::default();
+    let mut e = ExprParser::new().parse(&mut ctx.interner, src).unwrap();
+    e.deshadow();
+    match memo {
+        Some(memo) => {
+            memo.start();
+            while e != Expr::default() {
+                memo.step(ctx, &mut vms, &mut e).unwrap();
+            }
+        }
+        None => {
+            while e != Expr::default() {
+                ctx.small_step(&mut vms, &mut e).unwrap();
+            }
+        }
+    }
+    vms
+}
+
+#[test]
+fn test_memo_capacity() {
+    let mut ctx = builtin_context();
+    let src = "(_|(s|Z S S Z S S S mul Z S S Z S S S mul add Z S S Z S S S mul add))";
+    let expected = eval(&mut ctx, None, src);
+    let mut memo = Memo::default();
+    assert_eq!(eval(&mut ctx, Some(&mut memo), src), expected);
+    let report = memo.resolve(&ctx.interner);
+    assert_eq!(report.capacity, MEMO_CAPACITY);
+    assert_eq!(report.evicted, 0);
+    let entries = report.entries;
+    assert!(entries > 4, "{}", entries);
+    // A smaller table evicts the oldest calls, and still gives the same
+    // results.
+    let mut memo = Memo::with_capacity(4);
+    for _ in 0..2 {
+        assert_eq!(eval(&mut ctx, Some(&mut memo), src), expected);
+        let report = memo.resolve(&ctx.interner);
+        assert_eq!(report.entries, 4);
+        assert!(report.evicted > 0);
+    }
+    // A table that holds nothing memoizes nothing.
+    let mut memo = Memo::with_capacity(0);
+    assert_eq!(eval(&mut ctx, Some(&mut memo), src), expected);
+    let report = memo.resolve(&ctx.interner);
+    assert_eq!(report.entries, 0);
+    assert_eq!(report.steps_saved, 0);
+}
@@ -0,0 +1,120 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::builtin::TERM_DEF_SRCS;
+use crate::core::*;
+use crate::optimize::*;
+use crate::parse::*;
+
+fn define_terms(ctx: &mut Context, srcs: &[&str]) {
+    for src in srcs {
+        let term_def = TermDefParser::new().parse(&mut ctx.interner, src).unwrap();
+        ctx.define_term(term_def);
+    }
+}
+
+fn eval(ctx: &mut Context, mut e: Expr) -> (ValueMultistack, usize) {
+    let mut vms = ValueMultistack::default();
+    let mut steps = 0;
+    while e != Expr::default() {
+        ctx.small_step(&mut vms, &mut e).unwrap();
+        steps += 1;
+    }
+    (vms, steps)
+}
+
+#[test]
+fn test_optimize_expr() {
+    let mut ctx = Context::default();
+    define_terms(&mut ctx, &TERM_DEF_SRCS);
+    let cases = [
+        ("(a|push)(a|pop)", ""),
+        ("(a|pop)(b|push)(b|pop)(a|push)", ""),
+        ("(s|clone)(s|drop) swap", "swap"),
+        ("(s|[x])(s|[y]) (t|)", "(s|[x] [y])"),
+        ("(s|push (t|[x]))(s|(t|pop))", "(s|push (t|[x] pop))"),
+        ("[(a|push)(a|pop)]", "[(a|push)(a|pop)]"),
+        // `compose2` and `quote1` are small and use no stack contexts.
+        ("(s|compose2 quote1)", "(s|compose quote)"),
+        // `swap` uses stack contexts, and `add` is recursive.
+        ("swap add", "swap add"),
+    ];
+    for (src, expected) in cases.iter() {
+        let e = ExprParser::new().parse(&mut ctx.interner, src).unwrap();
+        let expected = ExprParser::new()
+            .parse(&mut ctx.interner, expected)
+            .unwrap();
+        assert_eq!(optimize(&ctx, &e), expected, "optimizing {}", src);
+    }
+}
+
+#[test]
+fn test_optimize_term() {
+    let mut ctx = Context::default();
+    define_terms(
+        &mut ctx,
+        &[
+            "{term dup = clone}",
+            "{term f = (a|push) dup (a|pop) (a|dup drop)}",
+            "{term g = g}",
+        ],
+    );
+    let f = TermSymbol(ctx.interner.get_or_intern_static("f"));
+    let g = TermSymbol(ctx.interner.get_or_intern_static("g"));
+    let undefined = TermSymbol(ctx.interner.get_or_intern_static("undefined"));
+    let expected = ExprParser::new()
+        .parse(&mut ctx.interner, "(a|push) clone (a|pop)")
+        .unwrap();
+    assert_eq!(optimize_term(&ctx, f), Some(TermDef(f, expected)));
+    assert_eq!(optimize_term(&ctx, g), Some(TermDef(g, Expr::Call(g))));
+    assert_eq!(optimize_term(&ctx, undefined), None);
+    let dup = TermSymbol(ctx.interner.get_or_intern_static("dup"));
+    assert_eq!(inlined_terms(&ctx, f), vec![dup]);
+    assert_eq!(inlined_terms(&ctx, g), vec![]);
+    assert_eq!(inlined_terms(&ctx, undefined), vec![]);
+}
+
+#[test]
+fn test_optimize_differential() {
+    let mut ctx = Context::default();
+    define_terms(&mut ctx, &TERM_DEF_SRCS);
+    define_terms(
+        &mut ctx,
+        &[
+            "{term dup = clone}",
+            "{term twice = dup compose2}",
+            "{term roundtrip = (a|push)(a|pop) (b|push clone)(b|drop pop)}",
+        ],
+    );
+    let srcs = [
+        "(_|(s|[a] [b] swap))",
+        "(_|(s|[a] [b] [c] quote3 twice apply))",
+        "(_|(s|True False or True and not))",
+        "(_|(s|Z S S Z S S S add))",
+        "(_|(s|Z S S Z S S mul))",
+        "(_|(s|[a] roundtrip (t|[b]) (t|push) (t|pop)))",
+    ];
+    let original = ctx.terms.clone();
+    let optimized: Map<TermSymbol, Expr> = original
+        .keys()
+        .map(|sym| (*sym, optimize_term(&ctx, *sym).unwrap().1))
+        .collect();
+    let mut total_steps = 0;
+    let mut total_optimized_steps = 0;
+    for src in srcs.iter() {
+        let e = ExprParser::new().parse(&mut ctx.interner, src).unwrap();
+        ctx.terms = original.clone();
+        let (vms, steps) = eval(&mut ctx, e.clone());
+        let optimized_e = optimize(&ctx, &e);
+        ctx.terms = optimized.clone();
+        let (optimized_vms, optimized_steps) = eval(&mut ctx, optimized_e);
+        assert_eq!(optimized_vms, vms, "evaluating {}", src);
+        assert!(optimized_steps <= steps, "evaluating {}", src);
+        total_steps += steps;
+        total_optimized_steps += optimized_steps;
+    }
+    assert!(total_optimized_steps < total_steps);
+}
@@ -5,7 +5,9 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use crate::core::*;
+use crate::interp::Definition;
 use crate::parse::*;
+use crate::testing::TestDef;
 
 #[test]
 fn test_parse_expr_empty() {
@@ -104,6 +106,24 @@ fn test_parse_expr_stack_context_call2() {
     );
 }
 
+#[test]
+fn test_parse_expr_comments() {
+    let interner = &mut Interner::default();
+    let expected = ExprParser::new().parse(interner, "[a] foo").unwrap();
+    for src in [
+        "-- comment\n[a] foo",
+        "--Comment with capital\n[a] foo",
+        "[a] --IntrPush\nfoo --",
+    ] {
+        assert_eq!(
+            ExprParser::new().parse(interner, src).unwrap(),
+            expected,
+            "Failed on {:?}",
+            src
+        );
+    }
+}
+
 #[test]
 fn test_parse_term_def() {
     let interner = &mut Interner::default();
@@ -118,6 +138,24 @@ fn test_parse_term_def() {
     );
 }
 
+#[test]
+fn test_parse_keywords_as_names() {
+    let interner = &mut Interner::default();
+    for name in ["test", "law"].iter() {
+        let src = format!("{{term {} = (law|test)}}", name);
+        assert_eq!(
+            TermDefParser::new().parse(interner, &src).unwrap(),
+            TermDef(
+                TermSymbol(interner.get(name).unwrap()),
+                Expr::StackContext(
+                    StackId(StackSymbol(interner.get("law").unwrap()), 0),
+                    Box::new(Expr::Call(TermSymbol(interner.get("test").unwrap()))),
+                )
+            )
+        );
+    }
+}
+
 #[test]
 fn test_parse_interp_items() {
     let interner = &mut Interner::default();
@@ -134,10 +172,10 @@ fn test_parse_interp_items() {
             .parse(interner, "{term empty = }")
             .unwrap(),
         (
-            vec![TermDef(
+            vec![Definition::Term(TermDef(
                 TermSymbol(interner.get("empty").unwrap()),
                 ExprParser::new().parse(interner, "").unwrap()
-            )],
+            ))],
             Expr::Compose(vec![])
         ),
     );
@@ -147,18 +185,38 @@ fn test_parse_interp_items() {
             .unwrap(),
         (
             vec![
-                TermDef(
+                Definition::Term(TermDef(
                     TermSymbol(interner.get("empty1").unwrap()),
                     ExprParser::new().parse(interner, "").unwrap()
-                ),
-                TermDef(
+                )),
+                Definition::Term(TermDef(
                     TermSymbol(interner.get("empty2").unwrap()),
                     ExprParser::new().parse(interner, "").unwrap()
-                )
+                ))
             ],
             Expr::Call(TermSymbol(interner.get("foo").unwrap()))
         ),
     );
+    assert_eq!(
+        InterpItemsParser::new()
+            .parse(interner, "{test t 5: ⟨s|v⟩ (s|foo) ⇓ ⟨s|⟩} foo")
+            .unwrap(),
+        (
+            vec![Definition::Test(TestDef {
+                name: "t".to_owned(),
+                max_steps: Some(5),
+                vms: ValueMultistackParser::new()
+                    .parse(interner, "⟨s|v⟩")
+                    .unwrap(),
+                e: ExprParser::new().parse(interner, "(s|foo)").unwrap(),
+                expected_vms: ValueMultistackParser::new()
+                    .parse(interner, "⟨s|⟩")
+                    .unwrap(),
+                expected_e: Expr::default(),
+            })],
+            Expr::Call(TermSymbol(interner.get("foo").unwrap()))
+        ),
+    );
 }
 
 #[test]
@@ -184,7 +242,7 @@ fn test_parse_value_multistack_empty() {
     let interner = &mut Interner::default();
     assert_eq!(
         ValueMultistackParser::new().parse(interner, "").unwrap(),
-        ValueMultistack(Map::default()),
+        ValueMultistack::default(),
     );
 }
 
@@ -195,10 +253,10 @@ fn test_parse_value_multistack_s_empty() {
         ValueMultistackParser::new()
             .parse(interner, "⟨s|⟩")
             .unwrap(),
-        ValueMultistack(crate::map! {
+        crate::map! {
             StackId(StackSymbol(interner.get("s").unwrap()), 0) =>
                 ValueStack(vec![]),
-        }),
+        },
     );
 }
 
@@ -209,10 +267,10 @@ fn test_parse_value_multistack_s_call() {
         ValueMultistackParser::new()
             .parse(interner, "⟨s|foo⟩")
             .unwrap(),
-        ValueMultistack(crate::map! {
+        crate::map! {
             StackId(StackSymbol(interner.get("s").unwrap()), 0) =>
                 ValueStack(vec![Value::Call(TermSymbol(interner.get("foo").unwrap()))]),
-        }),
+        },
     );
 }
 
@@ -223,10 +281,10 @@ fn test_parse_value_multistack_s_quote() {
         ValueMultistackParser::new()
             .parse(interner, "⟨s|[]⟩")
             .unwrap(),
-        ValueMultistack(crate::map! {
+        crate::map! {
             StackId(StackSymbol(interner.get("s").unwrap()), 0) =>
                 ValueStack(vec![Value::Quote(Box::new(Expr::default()))]),
-        }),
+        },
     );
 }
 
@@ -237,12 +295,12 @@ fn test_parse_value_multistack_s1_call_s2_call() {
         ValueMultistackParser::new()
             .parse(interner, "⟨s1|foo⟩ ⟨s2|bar⟩")
             .unwrap(),
-        ValueMultistack(crate::map! {
+        crate::map! {
             StackId(StackSymbol(interner.get("s1").unwrap()), 0) =>
                 ValueStack(vec![Value::Call(TermSymbol(interner.get("foo").unwrap()))]),
             StackId(StackSymbol(interner.get("s2").unwrap()), 0) =>
                 ValueStack(vec![Value::Call(TermSymbol(interner.get("bar").unwrap()))]),
-        }),
+        },
     );
 }
 
@@ -279,16 +337,16 @@ fn test_parse_small_step_assertion() {
             .parse(interner, "⟨s1|v1⟩ e1 ‒IntrPush⟶ ⟨s2|v2⟩ e2")
             .unwrap(),
         (
-            ValueMultistack(crate::map! {
+            crate::map! {
                 StackId(StackSymbol(interner.get("s1").unwrap()), 0) =>
                     ValueStack(vec![Value::Call(TermSymbol(interner.get("v1").unwrap()))]),
-            }),
+            },
             Expr::Call(TermSymbol(interner.get("e1").unwrap())),
             SmallStepRule::IntrPush,
-            ValueMultistack(crate::map! {
+            crate::map! {
                 StackId(StackSymbol(interner.get("s2").unwrap()), 0) =>
                     ValueStack(vec![Value::Call(TermSymbol(interner.get("v2").unwrap()))]),
-            }),
+            },
             Expr::Call(TermSymbol(interner.get("e2").unwrap())),
         )
     )
@@ -302,15 +360,15 @@ fn test_parse_big_step_assertion() {
             .parse(interner, "⟨s1|v1⟩ e1 ⇓ ⟨s2|v2⟩ e2")
             .unwrap(),
         (
-            ValueMultistack(crate::map! {
+            crate::map! {
                 StackId(StackSymbol(interner.get("s1").unwrap()), 0) =>
                     ValueStack(vec![Value::Call(TermSymbol(interner.get("v1").unwrap()))]),
-            }),
+            },
             Expr::Call(TermSymbol(interner.get("e1").unwrap())),
-            ValueMultistack(crate::map! {
+            crate::map! {
                 StackId(StackSymbol(interner.get("s2").unwrap()), 0) =>
                     ValueStack(vec![Value::Call(TermSymbol(interner.get("v2").unwrap()))]),
-            }),
+            },
             Expr::Call(TermSymbol(interner.get("e2").unwrap())),
         )
     )
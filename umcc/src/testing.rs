@@ -0,0 +1,246 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::core::*;
+use crate::display::*;
+use crate::stats::expr_size;
+use std::io;
+
+/// The number of small steps a test may take, unless it sets its own limit.
+pub const DEFAULT_MAX_STEPS: usize = 100_000;
+
+/// A test written in the language itself, as
+/// `{test name [max_steps]: ⟨..⟩ e ⇓ ⟨..⟩ e'}`, which asserts that evaluating
+/// `e` from the first multistack reaches the second multistack and `e'`,
+/// which is usually empty, within `max_steps` small steps. Expressions are
+/// compared with `Expr::alpha_eq`, so `e'` need not number its stacks the
+/// way deshadowing does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestDef {
+    pub name: String,
+    pub max_steps: Option<usize>,
+    pub vms: ValueMultistack,
+    pub e: Expr,
+    pub expected_vms: ValueMultistack,
+    pub expected_e: Expr,
+}
+
+/// How a test ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TestOutcome {
+    Passed {
+        steps: usize,
+    },
+    /// Evaluation finished without reaching the expected state.
+    Mismatch {
+        vms: ValueMultistack,
+        e: Expr,
+    },
+    Error {
+        step: usize,
+        err: EvalError,
+        vms: ValueMultistack,
+        e: Expr,
+    },
+    StepLimit {
+        max_steps: usize,
+        vms: ValueMultistack,
+        e: Expr,
+    },
+}
+
+impl TestDef {
+    /// Evaluate `e`, which is the test's expression with any missing stack
+    /// contexts added, until it reaches the expected state.
+    pub(crate) fn run(&self, ctx: &mut Context, mut e: Expr) -> TestOutcome {
+        let max_steps = self.max_steps.unwrap_or(DEFAULT_MAX_STEPS);
+        let mut expected_e = self.expected_e.clone();
+        expected_e.canonicalize();
+        let expected_size = expr_size(&expected_e);
+        // Checked before each step, so the cheap checks come first: an
+        // expression can only be alpha-equivalent to the expected one if it
+        // has the same size, and the multistacks are compared last.
+        let reached = |vms: &ValueMultistack, e: &Expr| {
+            let e_reached = if expected_e == Expr::default() {
+                *e == Expr::default()
+            } else if expr_size(e) == expected_size {
+                let mut e = e.clone();
+                e.canonicalize();
+                e == expected_e
+            } else {
+                false
+            };
+            e_reached && *vms == self.expected_vms
+        };
+        let mut vms = self.vms.clone();
+        let mut step = 0;
+        loop {
+            if reached(&vms, &e) {
+                return TestOutcome::Passed { steps: step };
+            }
+            if e == Expr::default() {
+                return TestOutcome::Mismatch { vms, e };
+            }
+            if step == max_steps {
+                return TestOutcome::StepLimit { max_steps, vms, e };
+            }
+            if let Err(err) = ctx.small_step(&mut vms, &mut e) {
+                return TestOutcome::Error { step, err, vms, e };
+            }
+            step += 1;
+        }
+    }
+}
+
+impl TestOutcome {
+    pub(crate) fn passed(&self) -> bool {
+        matches!(self, TestOutcome::Passed { .. })
+    }
+
+    /// Write why the test failed, if it did.
+    pub(crate) fn write_failure(
+        &self,
+        test: &TestDef,
+        interner: &Interner,
+        ascii: bool,
+        w: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        let show = |vms: &ValueMultistack| Notation {
+            value: vms.resolve(interner),
+            ascii,
+        };
+        match self {
+            TestOutcome::Passed { .. } => Ok(()),
+            TestOutcome::Mismatch { vms, .. } => {
+                write_diff(
+                    &test.expected_vms.resolve(interner),
+                    &vms.resolve(interner),
+                    ascii,
+                    w,
+                )?;
+                if test.expected_e != Expr::default() {
+                    // Evaluation finished, so the expression is empty.
+                    w.write_fmt(format_args!("  - {}\n", test.expected_e.resolve(interner)))?;
+                }
+                Ok(())
+            }
+            TestOutcome::Error { step, err, vms, e } => w.write_fmt(format_args!(
+                "  {} (after {} steps)\n  {}    {}\n",
+                err.resolve(interner),
+                step,
+                show(vms),
+                e.resolve(interner)
+            )),
+            TestOutcome::StepLimit { max_steps, vms, e } => w.write_fmt(format_args!(
+                "  Reached the limit of {} steps.\n  {}    {}\n",
+                max_steps,
+                show(vms),
+                e.resolve(interner)
+            )),
+        }
+    }
+}
+
+/// A judgement that `:assert` checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Judgement {
+    /// `⟨..⟩ e ‒Rule⟶ ⟨..⟩ e'`, which holds if a single small step of `e`
+    /// applies the rule and gives the second multistack and an expression
+    /// that is alpha-equivalent to `e'`.
+    SmallStep((ValueMultistack, Expr, SmallStepRule, ValueMultistack, Expr)),
+    /// `⟨..⟩ e ⇓ ⟨..⟩ e'`, which holds if evaluating `e` reaches the second
+    /// multistack and `e'`, like a test.
+    BigStep(TestDef),
+}
+
+/// Check that a single small step of `e` applies `rule` and gives
+/// `expected_vms` and `expected_e`, where `judgement` is
+/// `(vms, e, rule, expected_vms, expected_e)`, writing why it does not if it
+/// does not. Returns true if it does.
+pub(crate) fn check_small_step(
+    ctx: &mut Context,
+    judgement: (ValueMultistack, Expr, SmallStepRule, ValueMultistack, Expr),
+    ascii: bool,
+    w: &mut dyn io::Write,
+) -> io::Result<bool> {
+    let (mut vms, mut e, rule, expected_vms, expected_e) = judgement;
+    let actual_rule = match ctx.small_step(&mut vms, &mut e) {
+        Ok(rule) => rule,
+        Err(err) => {
+            w.write_fmt(format_args!("  {}\n", err.resolve(&ctx.interner)))?;
+            return Ok(false);
+        }
+    };
+    let interner = &ctx.interner;
+    let e_matches = e.alpha_eq(&expected_e);
+    if (actual_rule, &vms) == (rule, &expected_vms) && e_matches {
+        return Ok(true);
+    }
+    if actual_rule != rule {
+        let arrow = |rule| Notation {
+            value: SmallStepArrow(rule),
+            ascii,
+        };
+        w.write_fmt(format_args!(
+            "  - {}\n  + {}\n",
+            arrow(rule),
+            arrow(actual_rule)
+        ))?;
+    }
+    write_diff(
+        &expected_vms.resolve(interner),
+        &vms.resolve(interner),
+        ascii,
+        w,
+    )?;
+    if !e_matches {
+        w.write_fmt(format_args!(
+            "  - {}\n  + {}\n",
+            expected_e.resolve(interner),
+            e.resolve(interner)
+        ))?;
+    }
+    Ok(false)
+}
+
+/// Write the stacks that differ, with the expected values marked by `-` and
+/// the actual values by `+`.
+fn write_diff(
+    expected_vms: &ResolvedValueMultistack,
+    vms: &ResolvedValueMultistack,
+    ascii: bool,
+    w: &mut dyn io::Write,
+) -> io::Result<()> {
+    let mut sids: Vec<&ResolvedStackId> = expected_vms
+        .0
+        .keys()
+        .chain(
+            vms.0
+                .keys()
+                .filter(|sid| !expected_vms.0.contains_key(*sid)),
+        )
+        .collect();
+    sids.sort_unstable();
+    for sid in sids {
+        let expected = expected_vms.0.get(sid).filter(|vs| !vs.0.is_empty());
+        let actual = vms.0.get(sid).filter(|vs| !vs.0.is_empty());
+        if expected == actual {
+            continue;
+        }
+        for (sign, vs) in [("-", expected), ("+", actual)] {
+            if let Some(vs) = vs {
+                let stack =
+                    ResolvedValueMultistack(Some((sid.clone(), vs.clone())).into_iter().collect());
+                let stack = Notation {
+                    value: stack,
+                    ascii,
+                };
+                w.write_fmt(format_args!("  {} {}\n", sign, stack))?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,407 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::arena::*;
+use crate::core::*;
+use crate::debug::*;
+use crate::display::*;
+use crate::json::Json;
+use std::fmt;
+use std::io;
+use std::ops::RangeInclusive;
+
+/// Selects which small steps a trace prints. Steps are numbered from 1, and
+/// a step is printed only if it passes every filter.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceOptions {
+    /// Rules whose steps are not printed.
+    pub hide: Vec<SmallStepRule>,
+    /// If not empty, only steps using one of these rules are printed.
+    pub show: Vec<SmallStepRule>,
+    /// Only print steps taken inside a call to this term.
+    pub in_term: Option<String>,
+    /// Only print steps in this range.
+    pub steps: Option<RangeInclusive<usize>>,
+    /// Only print every `every`th step.
+    pub every: Option<usize>,
+    /// Instead of printing steps, print a tree of the calls made, each
+    /// summarized as a big step.
+    pub tree: bool,
+    /// In a call tree, leave out calls nested more deeply than this.
+    pub max_depth: Option<usize>,
+}
+
+/// A call made during a trace, which evaluated `expr` from `before` to
+/// `after`. `after` is `None` if the call did not return.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallNode {
+    pub expr: ResolvedExpr,
+    pub before: ResolvedValueMultistack,
+    pub after: Option<ResolvedValueMultistack>,
+    pub calls: Vec<CallNode>,
+}
+
+impl CallNode {
+    fn fmt_indented(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        let ascii = f.alternate();
+        let show = |value| Notation { value, ascii };
+        write!(
+            f,
+            "{:1$}{2}    {3} {4} ",
+            "",
+            2 * depth,
+            show(&self.before),
+            self.expr,
+            Notation {
+                value: BigStepArrow,
+                ascii
+            }
+        )?;
+        match &self.after {
+            Some(after) => write!(f, "{}", show(after))?,
+            None if ascii => write!(f, "...")?,
+            None => write!(f, "…")?,
+        }
+        for call in self.calls.iter() {
+            writeln!(f)?;
+            call.fmt_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays the call and the calls it made, one per line, indented by their
+/// depth. `{:#}` displays them in ASCII.
+impl fmt::Display for CallNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Call {
+    e: ExprRef,
+    before: InternedMultistack,
+    after: Option<InternedMultistack>,
+    calls: Vec<Call>,
+}
+
+impl Call {
+    fn resolve(&self, arena: &ExprArena, interner: &Interner) -> CallNode {
+        CallNode {
+            expr: arena.expr(self.e).resolve(interner),
+            before: arena.multistack(&self.before).resolve(interner),
+            after: self
+                .after
+                .as_ref()
+                .map(|vms| arena.multistack(vms).resolve(interner)),
+            calls: self
+                .calls
+                .iter()
+                .map(|c| c.resolve(arena, interner))
+                .collect(),
+        }
+    }
+}
+
+/// The state of a trace that is in progress.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Tracer {
+    options: TraceOptions,
+    in_term: Option<TermSymbol>,
+    pub(crate) step: usize,
+    pub(crate) calls: CallStack,
+    /// The calls of the tree that have not returned yet, outermost first.
+    open: Vec<Call>,
+    /// The outermost calls of the tree that have returned.
+    tree: Vec<Call>,
+    /// The number of calls in `tree` that have been taken by `new_calls`.
+    taken: usize,
+    /// The expressions and multistacks of the calls, which share most of
+    /// their subtrees.
+    arena: ExprArena,
+}
+
+impl Tracer {
+    pub(crate) fn new(options: TraceOptions, interner: &mut Interner) -> Self {
+        let in_term = options
+            .in_term
+            .as_ref()
+            .map(|name| TermSymbol(interner.get_or_intern(name)));
+        Self {
+            options,
+            in_term,
+            step: 0,
+            calls: CallStack::default(),
+            open: vec![],
+            tree: vec![],
+            taken: 0,
+            arena: ExprArena::default(),
+        }
+    }
+
+    /// Take one small step, and return its rule along with whether it should
+    /// be printed.
+    pub(crate) fn step(
+        &mut self,
+        ctx: &mut Context,
+        vms: &mut ValueMultistack,
+        e: &mut Expr,
+    ) -> Result<(SmallStepRule, bool), EvalError> {
+        let callee = next_callee(e);
+        let call = match callee {
+            Some(_) if self.options.tree => Some(Call {
+                e: self.arena.intern(redex(e)),
+                before: self.arena.intern_multistack(vms),
+                after: None,
+                calls: vec![],
+            }),
+            _ => None,
+        };
+        let rule = ctx.small_step(vms, e)?;
+        self.step += 1;
+        // A step is inside the term if the call was active before the step,
+        // which includes the step that returns from it, or after the step,
+        // which includes the `LitCall` itself.
+        let mut inside = self.is_inside();
+        if self.in_term.is_some() || self.options.tree {
+            self.calls.update(callee, rule, e, self.step);
+            inside = inside || self.is_inside();
+        }
+        if self.options.tree {
+            self.update_tree(call, vms);
+            return Ok((rule, false));
+        }
+        Ok((rule, inside && self.shows(rule)))
+    }
+
+    /// Bring the open calls of the tree in line with the call stack, after a
+    /// step that started `call` if it is not `None`.
+    fn update_tree(&mut self, call: Option<Call>, vms: &ValueMultistack) {
+        let depth = self.calls.depth();
+        let called = self.calls.frames().last().map(|frame| frame.step) == Some(self.step);
+        let outer_depth = if called { depth - 1 } else { depth };
+        while self.open.len() > outer_depth {
+            let mut call = self.open.pop().unwrap();
+            call.after = Some(self.arena.intern_multistack(vms));
+            self.close(call);
+        }
+        let shallow = match self.options.max_depth {
+            Some(max_depth) => depth <= max_depth,
+            None => true,
+        };
+        if called && shallow && self.open.len() == outer_depth {
+            self.open.extend(call);
+        }
+    }
+
+    fn close(&mut self, call: Call) {
+        match self.open.last_mut() {
+            Some(parent) => parent.calls.push(call),
+            None => self.tree.push(call),
+        }
+    }
+
+    /// Close the calls that have not returned, because evaluation stopped.
+    pub(crate) fn abandon(&mut self) {
+        while let Some(call) = self.open.pop() {
+            self.close(call);
+        }
+    }
+
+    /// The outermost calls that returned since the last call to `new_calls`.
+    pub(crate) fn new_calls(&mut self, interner: &Interner) -> Vec<CallNode> {
+        let calls = self.tree[self.taken..]
+            .iter()
+            .map(|call| call.resolve(&self.arena, interner))
+            .collect();
+        self.taken = self.tree.len();
+        calls
+    }
+
+    /// The outermost calls that have returned or been abandoned.
+    pub(crate) fn tree(&self, interner: &Interner) -> Vec<CallNode> {
+        self.tree
+            .iter()
+            .map(|call| call.resolve(&self.arena, interner))
+            .collect()
+    }
+
+    fn is_inside(&self) -> bool {
+        match self.in_term {
+            Some(sym) => self
+                .calls
+                .frames()
+                .iter()
+                .any(|frame| frame.callee == Callee::Term(sym)),
+            None => true,
+        }
+    }
+
+    fn shows(&self, rule: SmallStepRule) -> bool {
+        let options = &self.options;
+        if options.hide.contains(&rule) {
+            return false;
+        }
+        if !options.show.is_empty() && !options.show.contains(&rule) {
+            return false;
+        }
+        if let Some(steps) = &options.steps {
+            if !steps.contains(&self.step) {
+                return false;
+            }
+        }
+        if let Some(every) = options.every {
+            if let Some(r) = self.step.checked_rem(every) {
+                return r == 0;
+            }
+        }
+        true
+    }
+}
+
+/// Writes a JSON object for each small step taken, one per line.
+///
+/// Every object has the step number (`"step"`, counting from 1), the rule
+/// (`"rule"`), and the path to the redex (`"redex"`), which lists the index
+/// taken at each composition on the way down to it. Then, if the sink records
+/// full states, the objects have the resulting multistack and expression
+/// (`"multistack"` and `"expr"`) in the format of `Json`. Otherwise, they have
+/// the changes made by the step: `"stacks"` lists the stacks that changed as
+/// `{"stack", "pop", "push"}` objects, meaning that `pop` values were popped
+/// and then the `push` values were pushed, and `"replaced_by"` lists the
+/// expressions that replaced the first expression at the top level, where
+/// nested compositions are flattened.
+pub struct JsonTraceSink {
+    w: Box<dyn io::Write>,
+    full: bool,
+    step: usize,
+}
+
+impl JsonTraceSink {
+    pub fn new(w: Box<dyn io::Write>, full: bool) -> Self {
+        Self { w, full, step: 0 }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+
+    /// Take one small step and record it.
+    pub(crate) fn step(
+        &mut self,
+        ctx: &mut Context,
+        vms: &mut ValueMultistack,
+        e: &mut Expr,
+    ) -> io::Result<Result<SmallStepRule, EvalError>> {
+        let path = redex_path(e);
+        let stacks = match redex(e) {
+            Expr::StackContext(si, ei) => match &**ei {
+                Expr::StackContext(sii, _) => Some((*si, *sii)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let old_len = if self.full { 0 } else { top_items(e).len() };
+        let rule = match ctx.small_step(vms, e) {
+            Ok(rule) => rule,
+            Err(err) => return Ok(Err(err)),
+        };
+        self.step += 1;
+        write!(
+            self.w,
+            r#"{{"step":{},"rule":{},"redex":{}"#,
+            self.step,
+            Json(&rule),
+            Json(&path)
+        )?;
+        let interner = &ctx.interner;
+        if self.full {
+            write!(
+                self.w,
+                r#","multistack":{},"expr":{}"#,
+                Json(&vms.resolve(interner)),
+                Json(&e.resolve(interner))
+            )?;
+        } else {
+            write!(self.w, r#","stacks":["#)?;
+            let changes = stacks.map_or(vec![], |(si, sii)| stack_changes(rule, si, sii));
+            for (i, (s, pop, push)) in changes.into_iter().enumerate() {
+                let values = vms.get(&s).map_or(&[][..], |vs| &vs.0[..]);
+                let pushed: Vec<ResolvedValue> = values[values.len() - push..]
+                    .iter()
+                    .map(|v| v.resolve(interner))
+                    .collect();
+                write!(
+                    self.w,
+                    r#"{}{{"stack":{},"pop":{},"push":{}}}"#,
+                    if i == 0 { "" } else { "," },
+                    Json(&s.resolve(interner)),
+                    pop,
+                    Json(&pushed)
+                )?;
+            }
+            let items = top_items(e);
+            let replaced_by: Vec<ResolvedExpr> = items[..items.len() + 1 - old_len]
+                .iter()
+                .map(|e| e.resolve(interner))
+                .collect();
+            write!(self.w, r#"],"replaced_by":{}"#, Json(&replaced_by))?;
+        }
+        writeln!(self.w, "}}")?;
+        Ok(Ok(rule))
+    }
+}
+
+/// The indices taken at each composition on the way down to the redex.
+fn redex_path(mut e: &Expr) -> Vec<usize> {
+    let mut path = vec![];
+    while let Expr::Compose(es) = e {
+        match es.first() {
+            Some(first) => {
+                path.push(0);
+                e = first;
+            }
+            None => break,
+        }
+    }
+    path
+}
+
+/// The top-level expressions of `e`, with nested compositions flattened.
+fn top_items(e: &Expr) -> Vec<&Expr> {
+    fn push_items<'a>(e: &'a Expr, items: &mut Vec<&'a Expr>) {
+        match e {
+            Expr::Compose(es) => {
+                for e in es.iter() {
+                    push_items(e, items);
+                }
+            }
+            _ => items.push(e),
+        }
+    }
+    let mut items = vec![];
+    push_items(e, &mut items);
+    items
+}
+
+/// The stacks changed by a step that applied `rule` to a redex with the
+/// stack contexts `si` and `sii`, along with the number of values popped
+/// from and then pushed to each.
+fn stack_changes(rule: SmallStepRule, si: StackId, sii: StackId) -> Vec<(StackId, usize, usize)> {
+    match rule {
+        SmallStepRule::IntrPush => vec![(si, 1, 0), (sii, 0, 1)],
+        SmallStepRule::IntrPop => vec![(sii, 1, 0), (si, 0, 1)],
+        SmallStepRule::IntrClone | SmallStepRule::LitQuote => vec![(sii, 0, 1)],
+        SmallStepRule::IntrDrop | SmallStepRule::IntrApply => vec![(sii, 1, 0)],
+        SmallStepRule::IntrQuote => vec![(sii, 1, 1)],
+        SmallStepRule::IntrCompose => vec![(sii, 2, 1)],
+        SmallStepRule::LitCall
+        | SmallStepRule::StkCtxDistr
+        | SmallStepRule::StkCtx3Redund
+        | SmallStepRule::StkCtxEmpty => vec![],
+    }
+}
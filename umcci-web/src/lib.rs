@@ -3,9 +3,27 @@
 // This Source Code Form is subject to the terms of the Mozilla Public
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
-use umcc::interp::Interp;
+use umcc::interp::{self, Interp};
+use umcc::json::Json;
 use wasm_bindgen::prelude::*;
 
+fn parse_json(json: &str) -> JsValue {
+    js_sys::JSON::parse(json).unwrap()
+}
+
+fn state_json(interp: &Interp) -> String {
+    format!(
+        r#"{{"multistack":{},"expr":{}}}"#,
+        Json(&interp.value_multistack()),
+        Json(&interp.expr())
+    )
+}
+
+#[wasm_bindgen]
+pub fn is_incomplete(input: &str) -> bool {
+    interp::is_incomplete(input)
+}
+
 #[wasm_bindgen]
 pub struct Umcci {
     interp: Interp,
@@ -43,4 +61,77 @@ impl Umcci {
             .call1(&JsValue::null(), &JsValue::from(output))
             .unwrap();
     }
+
+    /// Take up to `max_steps` steps, calling `write_output` once with all of
+    /// their output.
+    pub fn interp_steps(&mut self, max_steps: usize, write_output: &js_sys::Function) {
+        self.buffer.clear();
+        for _ in 0..max_steps {
+            if self.interp.is_done() {
+                break;
+            }
+            self.interp.interp_step(&mut self.buffer).unwrap();
+        }
+        let output = unsafe { std::str::from_utf8_unchecked(&self.buffer[..]) };
+        write_output
+            .call1(&JsValue::null(), &JsValue::from(output))
+            .unwrap();
+    }
+
+    /// The defined terms, as an array of `{name, expr}` objects sorted by
+    /// name.
+    pub fn definitions(&self) -> JsValue {
+        let mut json = String::from("[");
+        for (i, (name, e)) in self.interp.definitions().iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json.push_str(&format!(r#"{{"name":{},"expr":{}}}"#, Json(name), Json(e)));
+        }
+        json.push(']');
+        parse_json(&json)
+    }
+
+    /// The current value multistack and the expression remaining to be
+    /// evaluated (or null), as a `{multistack, expr}` object.
+    pub fn state(&self) -> JsValue {
+        parse_json(&state_json(&self.interp))
+    }
+
+    /// Like `interp_step`, but also return the step taken as a
+    /// `{rule, error, before, after}` object, where `before` and `after` are
+    /// states as returned by `state`. `rule` and `error` are null if no small
+    /// step was taken.
+    pub fn interp_step_json(&mut self, write_output: &js_sys::Function) -> JsValue {
+        let before = state_json(&self.interp);
+        self.interp_step(write_output);
+        let after = state_json(&self.interp);
+        let (rule, error) = match self.interp.last_step() {
+            Some(Ok(rule)) => (Some(rule), None),
+            Some(Err(err)) => (None, Some(err)),
+            None => (None, None),
+        };
+        parse_json(&format!(
+            r#"{{"rule":{},"error":{},"before":{},"after":{}}}"#,
+            Json(&rule),
+            Json(&error),
+            before,
+            after
+        ))
+    }
+
+    /// The calls made by the last command, if it was a `:trace -tree`, as
+    /// an array of `{expr, before, after, calls}` objects.
+    pub fn call_tree(&self) -> JsValue {
+        parse_json(&Json(&self.interp.call_tree()).to_string())
+    }
+
+    pub fn interrupt(&mut self, write_output: &js_sys::Function) {
+        self.buffer.clear();
+        self.interp.interrupt(&mut self.buffer).unwrap();
+        let output = unsafe { std::str::from_utf8_unchecked(&self.buffer[..]) };
+        write_output
+            .call1(&JsValue::null(), &JsValue::from(output))
+            .unwrap();
+    }
 }
@@ -4,9 +4,57 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-import init, { Umcci } from "./pkg/umcci_web.js";
+import init, { is_incomplete } from "./pkg/umcci_web.js";
 
 const PROMPT = "\n>>> ";
+const CONTINUATION_PROMPT = "\n... ";
+
+function expr_to_string(expr) {
+  switch (expr.type) {
+    case "compose":
+      return expr.exprs
+        .map((e) => (e.type == "compose" ? `(${expr_to_string(e)})` : expr_to_string(e)))
+        .join(" ");
+    case "intrinsic":
+    case "call":
+      return expr.name;
+    case "quote":
+      return `[${expr_to_string(expr.expr)}]`;
+    case "stack_context":
+      return `(${expr.stack}|${expr_to_string(expr.expr)})`;
+  }
+}
+
+function multistack_to_string(multistack) {
+  return multistack
+    .map(({ stack, values }) => `⟨${stack}|${values.map(expr_to_string).join(" ")}⟩`)
+    .join(" ");
+}
+
+// Renders a call tree from `Umcci.call_tree` as nested `<details>` elements,
+// each summarizing a call as `⟨before⟩ e ⇓ ⟨after⟩`.
+function call_tree_element(calls) {
+  let list = document.createElement("div");
+  for (let call of calls) {
+    let summary = document.createElement("summary");
+    let after = call.after === null ? "…" : multistack_to_string(call.after);
+    summary.textContent = `${multistack_to_string(call.before)}    ${expr_to_string(
+      call.expr
+    )} ⇓ ${after}`;
+    let details = document.createElement("details");
+    details.appendChild(summary);
+    if (call.calls.length > 0) {
+      let children = call_tree_element(call.calls);
+      children.style = "margin-left: 1.5em;";
+      details.appendChild(children);
+    } else {
+      // Leaf calls have nothing to expand.
+      summary.style = "list-style: none; margin-left: 1em;";
+    }
+    list.appendChild(details);
+  }
+  return list;
+}
 
 async function run() {
   await init();
@@ -40,29 +88,94 @@ async function run() {
     spellcheck: false;
   `;
 
-  textarea.value =
-    `\
+  let stop_button = document.createElement("button");
+  document.body.appendChild(stop_button);
+  stop_button.textContent = "Stop";
+  stop_button.disabled = true;
+  stop_button.style = `
+    position: absolute;
+    top: 10px;
+    right: 20px;
+  `;
+
+  // Shows the tree from the last `:trace -tree`.
+  let tree_panel = document.createElement("div");
+  document.body.appendChild(tree_panel);
+  tree_panel.hidden = true;
+  tree_panel.style = `
+    position: absolute;
+    top: 40px;
+    right: 20px;
+    width: 50vw;
+    max-height: calc(100vh - 60px);
+    overflow: auto;
+    padding: 5px;
+    border: 1px solid gray;
+    background: white;
+    font-family: monospace,monospace;
+    font-size: 10pt;
+    white-space: pre;
+  `;
+
+  function show_tree(tree) {
+    let close_button = document.createElement("button");
+    close_button.textContent = "Close";
+    close_button.style = "float: right;";
+    close_button.addEventListener("click", () => {
+      tree_panel.hidden = true;
+      textarea.focus();
+    });
+    tree_panel.replaceChildren(close_button, call_tree_element(tree));
+    tree_panel.hidden = false;
+  }
+
+  textarea.value = `\
 Untyped Multistack Concatenative Calculus Interpreter (UMCCI)
 Type ":help" to see the available commands.
-` + PROMPT;
+`;
 
   function write_output(output) {
     textarea.value += output;
     textarea.scrollTop = textarea.scrollHeight;
   }
 
-  let umcci = new Umcci();
+  // The interpreter runs in a worker, and is busy until it sends "done".
+  let busy = true;
+  let worker = new Worker("worker.js", { type: "module" });
+  worker.onmessage = (ev) => {
+    switch (ev.data.type) {
+      case "ready":
+      case "done":
+        busy = false;
+        stop_button.disabled = true;
+        write_output(PROMPT);
+        break;
+      case "output":
+        write_output(ev.data.output);
+        break;
+      case "tree":
+        show_tree(ev.data.tree);
+        break;
+    }
+  };
 
-  function step() {
-    if (umcci.is_done()) {
-      textarea.value += PROMPT;
-      textarea.scrollTop = textarea.scrollHeight;
-    } else {
-      umcci.interp_step(write_output);
-      setTimeout(step);
+  function start(input) {
+    busy = true;
+    stop_button.disabled = false;
+    worker.postMessage({ type: "start", input });
+  }
+
+  function stop() {
+    if (busy) {
+      worker.postMessage({ type: "stop" });
     }
   }
 
+  stop_button.addEventListener("click", () => {
+    stop();
+    textarea.focus();
+  });
+
   textarea.addEventListener("keydown", (ev) => {
     if (ev.key == "Enter") {
       if (
@@ -70,13 +183,22 @@ Type ":help" to see the available commands.
         textarea.selectionEnd === textarea.value.length
       ) {
         ev.preventDefault();
-        textarea.value += "\n";
-        let input = textarea.value.slice(
-          textarea.value.lastIndexOf(PROMPT) + PROMPT.length,
-          textarea.selectionEnd
-        );
-        umcci.interp_start(input, write_output);
-        setTimeout(step);
+        if (busy) {
+          return;
+        }
+        let input = textarea.value
+          .slice(
+            textarea.value.lastIndexOf(PROMPT) + PROMPT.length,
+            textarea.selectionEnd
+          )
+          .split(CONTINUATION_PROMPT)
+          .join("\n");
+        if (is_incomplete(input)) {
+          write_output(CONTINUATION_PROMPT);
+          return;
+        }
+        write_output("\n");
+        start(input);
       }
     }
     if (
@@ -85,8 +207,7 @@ Type ":help" to see the available commands.
       textarea.selectionStart === textarea.selectionEnd &&
       textarea.selectionEnd === textarea.value.length
     ) {
-      umcci.interp_start("", write_output);
-      setTimeout(step);
+      stop();
     }
   });
 }
@@ -0,0 +1,77 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Runs the interpreter off of the main thread, so that long evaluations
+// don't freeze the page and can be stopped.
+//
+// Messages received:
+//   { type: "start", input }   interpret `input`
+//   { type: "stop" }           interrupt the current evaluation
+// Messages sent:
+//   { type: "ready" }          the interpreter is loaded
+//   { type: "output", output } output of the interpreter
+//   { type: "tree", tree }     the calls made by a `:trace -tree`
+//   { type: "done" }           the current input is finished
+
+import init, { Umcci } from "./pkg/umcci_web.js";
+
+// Steps taken between checks for a "stop" message.
+const STEPS_PER_BATCH = 10000;
+
+let umcci = null;
+let running = false;
+
+function write_output(output) {
+  if (output.length > 0) {
+    postMessage({ type: "output", output });
+  }
+}
+
+function done() {
+  running = false;
+  let tree = umcci.call_tree();
+  if (tree.length > 0) {
+    postMessage({ type: "tree", tree });
+  }
+  postMessage({ type: "done" });
+}
+
+function run_batch() {
+  if (!running) {
+    return;
+  }
+  if (umcci.is_done()) {
+    done();
+  } else {
+    umcci.interp_steps(STEPS_PER_BATCH, write_output);
+    // Yield to the event loop so that "stop" messages are handled.
+    setTimeout(run_batch);
+  }
+}
+
+onmessage = (ev) => {
+  switch (ev.data.type) {
+    case "start":
+      umcci.interp_start(ev.data.input, write_output);
+      running = true;
+      run_batch();
+      break;
+    case "stop":
+      if (running) {
+        umcci.interrupt(write_output);
+        done();
+      }
+      break;
+  }
+};
+
+async function run() {
+  await init();
+  umcci = new Umcci();
+  postMessage({ type: "ready" });
+}
+
+run();
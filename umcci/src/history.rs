@@ -0,0 +1,113 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// The maximum number of entries kept in the history file.
+const HISTORY_SIZE: usize = 1000;
+
+/// Input history that persists across sessions.
+///
+/// Each entry is stored on its own line of the history file, with newlines
+/// and backslashes escaped, so that multi-line inputs are kept as single
+/// entries. Entries are de-duplicated, keeping only the most recent copy.
+pub struct History {
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+}
+
+impl History {
+    /// Load the history from the user's data directory. If there is no data
+    /// directory, the history is kept in memory only.
+    pub fn load() -> Self {
+        Self::load_from(dirs::data_dir().map(|dir| dir.join("umcci").join("history")))
+    }
+
+    /// Load the history from `path`, or keep it in memory only if there is
+    /// no path.
+    pub fn load_from(path: Option<PathBuf>) -> Self {
+        let entries = match &path {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(contents) => contents.lines().map(unescape).collect(),
+                Err(_) => vec![],
+            },
+            None => vec![],
+        };
+        let mut history = Self { path, entries };
+        history.truncate();
+        history
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Add an entry as the most recent one, removing any earlier copy of it,
+    /// and save the history.
+    pub fn add(&mut self, entry: String) -> io::Result<()> {
+        if entry.trim().is_empty() {
+            return Ok(());
+        }
+        self.entries.retain(|e| *e != entry);
+        self.entries.push(entry);
+        self.truncate();
+        self.save()
+    }
+
+    fn truncate(&mut self) {
+        if self.entries.len() > HISTORY_SIZE {
+            self.entries.drain(..self.entries.len() - HISTORY_SIZE);
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut contents = String::new();
+            for entry in self.entries.iter() {
+                contents.push_str(&escape(entry));
+                contents.push('\n');
+            }
+            fs::write(path, contents)?;
+        }
+        Ok(())
+    }
+}
+
+fn escape(entry: &str) -> String {
+    let mut result = String::with_capacity(entry.len());
+    for c in entry.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn unescape(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some(c) => result.push(c),
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
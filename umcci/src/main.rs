@@ -4,24 +4,117 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use linefeed::{Interface, ReadResult};
+mod history;
+#[cfg(test)]
+mod test_history;
+mod run;
+
+use history::History;
+use linefeed::{DefaultTerminal, Interface, ReadResult};
+use std::env;
 use std::error::Error;
 use std::io::stdout;
-use umcc::interp::Interp;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use umcc::interp::{is_incomplete, Interp};
+
+const PROMPT: &str = "\n>>> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+static HISTORY_HELP: &str = "   :history                 list the input history
+   :history <n>             re-run entry <n> of the input history
+";
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut interp = Interp::default();
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let interrupted = interrupted.clone();
+        ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))?;
+    }
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(|arg| arg.as_str()) {
+        None => repl(interrupted),
+        Some("run") => run::run(&args[1..], interrupted),
+        Some("test") => run::test(&args[1..], interrupted),
+        Some(_) => {
+            eprint!("{}", run::USAGE);
+            std::process::exit(2);
+        }
+    }
+}
 
+fn repl(interrupted: Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
+    let mut interp = Interp::default();
+    interp.set_extra_help(HISTORY_HELP);
     println!("Untyped Multistack Concatenative Calculus Interpreter (UMCCI)");
     println!("Type \":help\" to see the available commands.");
     let reader = Interface::new("umcci")?;
-    reader.set_prompt("\n>>> ")?;
-    while let ReadResult::Input(input) = reader.read_line()? {
-        reader.add_history(input.clone());
-        interp.interp_start(input.as_str(), &mut stdout()).unwrap();
+    reader.set_prompt(PROMPT)?;
+    let mut history = History::load();
+    sync_history(&reader, &history);
+    let mut input = String::new();
+    while let ReadResult::Input(line) = reader.read_line()? {
+        if !input.is_empty() {
+            input.push('\n');
+        }
+        input.push_str(&line);
+        if is_incomplete(&input) {
+            reader.set_prompt(CONTINUATION_PROMPT)?;
+            continue;
+        }
+        reader.set_prompt(PROMPT)?;
+        let mut command = std::mem::take(&mut input);
+        if let Some(arg) = history_command_arg(&command) {
+            let entries = history.entries();
+            if arg.is_empty() {
+                for (i, entry) in entries.iter().enumerate() {
+                    println!("{:5}  {}", i + 1, entry.replace('\n', "\n       "));
+                }
+                continue;
+            }
+            match arg.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= entries.len() => {
+                    command = entries[n - 1].clone();
+                    println!("{}", command);
+                }
+                _ => {
+                    println!("No history entry `{}`.", arg);
+                    continue;
+                }
+            }
+        }
+        if let Err(err) = history.add(command.clone()) {
+            println!("Failed to save history: {}", err);
+        }
+        sync_history(&reader, &history);
+        interrupted.store(false, Ordering::SeqCst);
+        interp
+            .interp_start(command.as_str(), &mut stdout())
+            .unwrap();
         while !interp.is_done() {
+            if interrupted.swap(false, Ordering::SeqCst) {
+                interp.interrupt(&mut stdout()).unwrap();
+                break;
+            }
             interp.interp_step(&mut stdout()).unwrap();
         }
     }
     Ok(())
 }
+
+/// If `input` is a `:history` command, return its (possibly empty) argument.
+fn history_command_arg(input: &str) -> Option<&str> {
+    let rest = input.trim().strip_prefix(":history")?;
+    if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
+fn sync_history(reader: &Interface<DefaultTerminal>, history: &History) {
+    reader.clear_history();
+    for entry in history.entries() {
+        reader.add_history(entry.clone());
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, stdout, BufWriter, Write};
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use umcc::interp::Interp;
+use umcc::trace::JsonTraceSink;
+
+pub(crate) static USAGE: &str = "\
+Usage:
+    umcci                       start the interactive interpreter
+    umcci run <file> [options]  evaluate the definitions and expression in <file>
+    umcci test <file>           run the tests defined in <file>
+
+Options for `run`:
+    --trace-json <path>         write each small step to <path> as JSON Lines,
+                                compressed with gzip if <path> ends in `.gz`
+    --trace-diff                write only what each step changed, instead of
+                                the whole multistack and expression
+    --profile <path>            write the number of steps taken in each stack
+                                of calls to <path>, as collapsed stacks for
+                                flamegraph tools
+    --ascii                     write <s|...>, -Rule-> and ==> instead of
+                                Unicode symbols
+";
+
+struct RunArgs {
+    path: String,
+    trace_json: Option<String>,
+    trace_diff: bool,
+    profile: Option<String>,
+    ascii: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<RunArgs, String> {
+    let mut path = None;
+    let mut trace_json = None;
+    let mut trace_diff = false;
+    let mut profile = None;
+    let mut ascii = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace-json" => match args.next() {
+                Some(out) => trace_json = Some(out.clone()),
+                None => return Err("Missing path after `--trace-json`.".to_owned()),
+            },
+            "--trace-diff" => trace_diff = true,
+            "--ascii" => ascii = true,
+            "--profile" => match args.next() {
+                Some(out) => profile = Some(out.clone()),
+                None => return Err("Missing path after `--profile`.".to_owned()),
+            },
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument `{}`.", arg)),
+        }
+    }
+    match path {
+        Some(path) => Ok(RunArgs {
+            path,
+            trace_json,
+            trace_diff,
+            profile,
+            ascii,
+        }),
+        None => Err("Missing file to run.".to_owned()),
+    }
+}
+
+enum TraceWriter {
+    Plain(BufWriter<File>),
+    Gzip(BufWriter<GzEncoder<File>>),
+}
+
+/// The file a JSON trace is written to. It is shared with the trace sink, so
+/// that it can be finished, and any error reported, once the sink is done.
+#[derive(Clone)]
+struct TraceFile(Rc<RefCell<Option<TraceWriter>>>);
+
+impl TraceFile {
+    fn create(path: &str) -> io::Result<Self> {
+        let file = File::create(path)?;
+        let writer = if path.ends_with(".gz") {
+            TraceWriter::Gzip(BufWriter::new(GzEncoder::new(file, Compression::default())))
+        } else {
+            TraceWriter::Plain(BufWriter::new(file))
+        };
+        Ok(Self(Rc::new(RefCell::new(Some(writer)))))
+    }
+
+    /// Flush the file and, if it is compressed, write the gzip trailer.
+    fn finish(&self) -> io::Result<()> {
+        match self.0.borrow_mut().take() {
+            Some(TraceWriter::Plain(mut w)) => w.flush(),
+            Some(TraceWriter::Gzip(w)) => {
+                let encoder = w.into_inner().map_err(|err| err.into_error())?;
+                encoder.finish()?.flush()
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for TraceFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut *self.0.borrow_mut() {
+            Some(TraceWriter::Plain(w)) => w.write(buf),
+            Some(TraceWriter::Gzip(w)) => w.write(buf),
+            None => Err(io::Error::other("the trace file is already finished")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut *self.0.borrow_mut() {
+            Some(TraceWriter::Plain(w)) => w.flush(),
+            Some(TraceWriter::Gzip(w)) => w.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Evaluate a file, then run the tests it defines and exit with a non-zero
+/// status if any failed.
+pub(crate) fn test(args: &[String], interrupted: Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
+    let path = match args {
+        [path] => path,
+        _ => {
+            eprint!("Expected a single file to test.\n\n{}", USAGE);
+            process::exit(2);
+        }
+    };
+    let input = fs::read_to_string(path)?;
+    let mut interp = Interp::default();
+    interp.interp_start(&input, &mut stdout())?;
+    if interp.parse_failed() {
+        process::exit(1);
+    }
+    while !interp.is_done() {
+        if interrupted.swap(false, Ordering::SeqCst) {
+            interp.interrupt(&mut stdout())?;
+            process::exit(1);
+        }
+        interp.interp_step(&mut stdout())?;
+    }
+    if !interp.run_tests(&mut stdout())? {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Evaluate a file like the interactive interpreter would, then exit with a
+/// non-zero status if evaluation failed.
+pub(crate) fn run(args: &[String], interrupted: Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
+    let args = match parse_args(args) {
+        Ok(args) => args,
+        Err(err) => {
+            eprint!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    let input = fs::read_to_string(&args.path)?;
+    let mut interp = Interp::default();
+    let trace_file = match &args.trace_json {
+        Some(path) => Some(TraceFile::create(path)?),
+        None => None,
+    };
+    if let Some(file) = &trace_file {
+        let sink = JsonTraceSink::new(Box::new(file.clone()), !args.trace_diff);
+        interp.set_json_trace(Some(sink));
+    }
+    interp.set_profiling(args.profile.is_some());
+    interp.set_ascii(args.ascii);
+    interp.interp_start(&input, &mut stdout())?;
+    let mut failed = interp.parse_failed();
+    while !interp.is_done() {
+        if interrupted.swap(false, Ordering::SeqCst) {
+            interp.interrupt(&mut stdout())?;
+            failed = true;
+            break;
+        }
+        interp.interp_step(&mut stdout())?;
+        failed = matches!(interp.last_step(), Some(Err(_)));
+    }
+    if let Some(mut sink) = interp.set_json_trace(None) {
+        sink.flush()?;
+    }
+    if let Some(file) = &trace_file {
+        file.finish()?;
+    }
+    if let Some(path) = &args.profile {
+        let mut file = BufWriter::new(File::create(path)?);
+        interp
+            .profile()
+            .unwrap_or_default()
+            .write_collapsed(&mut file)?;
+        file.flush()?;
+    }
+    if failed {
+        process::exit(1);
+    }
+    Ok(())
+}
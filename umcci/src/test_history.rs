@@ -0,0 +1,83 @@
+// Copyright (c) 2021 Scott J Maddox
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::history::History;
+use std::fs;
+use std::path::PathBuf;
+
+/// A history file of its own for each test, since tests run in parallel.
+fn history_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir()
+        .join(format!("umcci-test-{}", std::process::id()))
+        .join(name);
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_history_round_trip() {
+    let path = history_path("round_trip");
+    let entries = [
+        "(s|[v] clone)",
+        "{term dup =\n    clone\n}",
+        "-- a \\ backslash, and \\n written out",
+        "\\",
+        "ends with a backslash \\",
+        "carriage\r\nreturn",
+    ];
+    let mut history = History::load_from(Some(path.clone()));
+    for entry in entries.iter() {
+        history.add(entry.to_string()).unwrap();
+    }
+    // Each entry is one line of the file.
+    let contents = fs::read_to_string(&path).unwrap();
+    assert_eq!(contents.lines().count(), entries.len());
+    let history = History::load_from(Some(path.clone()));
+    assert_eq!(history.entries(), &entries[..]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_history_dedup() {
+    let path = history_path("dedup");
+    let mut history = History::load_from(Some(path.clone()));
+    for entry in ["a", "b", "a", "  ", "", "c", "b"].iter() {
+        history.add(entry.to_string()).unwrap();
+    }
+    // Blank entries are skipped, and only the most recent copy is kept.
+    assert_eq!(history.entries(), &["a", "c", "b"][..]);
+    let history = History::load_from(Some(path.clone()));
+    assert_eq!(history.entries(), &["a", "c", "b"][..]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_history_cap() {
+    let path = history_path("cap");
+    let mut history = History::load_from(Some(path.clone()));
+    for i in 0..1005 {
+        history.add(format!("(s|[v{}])", i)).unwrap();
+    }
+    let expected: Vec<String> = (5..1005).map(|i| format!("(s|[v{}])", i)).collect();
+    assert_eq!(history.entries(), &expected[..]);
+    let history = History::load_from(Some(path.clone()));
+    assert_eq!(history.entries(), &expected[..]);
+    fs::remove_file(&path).unwrap();
+    // A file with too many entries is cut down when it is loaded.
+    let contents: String = (0..1500).map(|i| format!("{}\n", i)).collect();
+    fs::write(&path, contents).unwrap();
+    let history = History::load_from(Some(path.clone()));
+    assert_eq!(history.entries().len(), 1000);
+    assert_eq!(history.entries()[0], "500");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_history_in_memory() {
+    let mut history = History::load_from(None);
+    history.add("(s|[v])".to_owned()).unwrap();
+    assert_eq!(history.entries(), &["(s|[v])"][..]);
+}